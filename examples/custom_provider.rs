@@ -0,0 +1,117 @@
+use aipi::client::{LlmClient, LlmClientError};
+use aipi::message::{Message, MessageBundle, StopReason, Usage};
+use aipi::models::{Model, ModelConfig, ModelConfigBuilder, Role};
+use aipi::provider::{Provider, ProviderResponse};
+use async_trait::async_trait;
+use reqwest::{RequestBuilder, Response};
+use serde::Deserialize;
+use serde_json::json;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, fmt as trace_fmt};
+
+/// A backend with its own (made up) api: POST {base}/complete {"model", "prompt"} -> {"completion", "tokens_in", "tokens_out"}
+#[derive(Debug)]
+struct PlainCompletion {
+    base_url: String,
+}
+
+#[derive(Deserialize)]
+struct CompletionResponse {
+    completion: String,
+    tokens_in: u64,
+    tokens_out: u64,
+}
+
+#[async_trait]
+impl Provider for PlainCompletion {
+    fn name(&self) -> &str {
+        "plain-completion"
+    }
+
+    fn endpoint(&self, _config: &ModelConfig, _stream: bool) -> String {
+        format!("{}/complete", self.base_url)
+    }
+
+    async fn headers(
+        &self,
+        _config: &ModelConfig,
+        request: RequestBuilder,
+    ) -> Result<RequestBuilder, LlmClientError> {
+        Ok(request.header("content-type", "application/json"))
+    }
+
+    // flatten the conversation into a single transcript prompt
+    fn encode_request(
+        &self,
+        config: &ModelConfig,
+        history: &[MessageBundle],
+        next: &MessageBundle,
+        _stream: bool,
+    ) -> Result<String, LlmClientError> {
+        let mut prompt = config.system_prompt.clone().unwrap_or_default();
+        for bundle in history.iter().chain(std::iter::once(next)) {
+            let speaker = bundle.message.role.as_string(&config.model);
+            prompt.push_str(&format!("\n{speaker}: {}", bundle.message.text()));
+        }
+        prompt.push_str("\nassistant:");
+        Ok(json!({ "model": config.model.to_model_string(), "prompt": prompt }).to_string())
+    }
+
+    async fn decode_response(
+        &self,
+        _config: &ModelConfig,
+        response: Response,
+    ) -> Result<ProviderResponse, LlmClientError> {
+        let body = response
+            .json::<CompletionResponse>()
+            .await
+            .map_err(|e| LlmClientError::ParseResponse(e.to_string()))?;
+        Ok(ProviderResponse {
+            message: Message::from_ai(body.completion),
+            usage: Some(Usage {
+                input_tokens: body.tokens_in,
+                output_tokens: body.tokens_out,
+                ..Usage::default()
+            }),
+            // this backend doesn't say, a reply is always a complete one
+            stop_reason: Some(StopReason::EndTurn),
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    setup_tracing();
+    let model = Model::Custom {
+        provider: "plain-completion".to_string(),
+        model_id: "house-model-v2".to_string(),
+    };
+    let config = ModelConfigBuilder::new(model)
+        .with_system_prompt("You are terse.".to_string())
+        .build()
+        .expect("valid config");
+
+    let mut client = LlmClient::new(config).with_provider(PlainCompletion {
+        base_url: std::env::var("PLAIN_COMPLETION_URL")
+            .unwrap_or_else(|_| "http://localhost:8000".to_string()),
+    });
+
+    client
+        .send_chat_message(Message::from_user("Name a prime number.".to_string()))
+        .await?;
+    let reply = client.message_history.last().expect("reply in history");
+    assert_eq!(reply.message.role, Role::Ai);
+    println!("{}", reply.message.text());
+
+    client.log_message_history();
+
+    Ok(())
+}
+
+fn setup_tracing() {
+    let filter = EnvFilter::try_new("info,aipi=info,reqwest=warn,hyper=warn").unwrap();
+    let json_layer = trace_fmt::layer().json().with_filter(filter);
+
+    tracing_subscriber::registry().with(json_layer).init();
+}
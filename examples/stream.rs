@@ -0,0 +1,43 @@
+use std::io::Write;
+
+use aipi::client::LlmClient;
+use aipi::message::{Message, StreamDelta};
+use aipi::models::{ClaudeVersion, Model, ModelConfigBuilder};
+use futures::StreamExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, fmt as trace_fmt};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    setup_tracing();
+    let config = ModelConfigBuilder::new(Model::Claude(ClaudeVersion::Sonnet4))
+        .with_max_tokens(1024)
+        .build()
+        .expect("valid config & env");
+
+    let mut client = LlmClient::new(config);
+
+    let message = Message::from_user("Tell me a short story about a lighthouse.".to_string());
+    let mut stream = client.send_chat_message_stream(message).await?;
+    while let Some(delta) = stream.next().await {
+        match delta? {
+            StreamDelta::Text(text) => print!("{text}"),
+            StreamDelta::ToolCall(call) => print!("\n[{}({})]", call.name, call.arguments),
+        }
+        std::io::stdout().flush()?;
+    }
+    println!();
+    drop(stream);
+
+    client.log_message_history();
+
+    Ok(())
+}
+
+fn setup_tracing() {
+    let filter = EnvFilter::try_new("info,aipi=info,reqwest=warn,hyper=warn").unwrap();
+    let json_layer = trace_fmt::layer().json().with_filter(filter);
+
+    tracing_subscriber::registry().with(json_layer).init();
+}
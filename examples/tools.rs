@@ -0,0 +1,63 @@
+use aipi::client::LlmClient;
+use aipi::message::Message;
+use aipi::models::{ClaudeVersion, Model, ModelConfigBuilder};
+use aipi::tool::{ToolCall, ToolDefinition, ToolResult};
+use serde_json::json;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, fmt as trace_fmt};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    setup_tracing();
+    let weather = ToolDefinition::new(
+        "get_weather".to_string(),
+        "Get the current weather for a city".to_string(),
+        json!({
+            "type": "object",
+            "properties": { "city": { "type": "string" } },
+            "required": ["city"]
+        }),
+    );
+    let config = ModelConfigBuilder::new(Model::Claude(ClaudeVersion::Sonnet4))
+        .with_max_tokens(1024)
+        .with_tool(weather)
+        .build()
+        .expect("valid config & env");
+
+    let mut client = LlmClient::new(config);
+
+    let message = Message::from_user("What's the weather like in Lisbon?".to_string());
+    client.send_chat_message(message).await?;
+
+    // keep answering tool calls until the model responds with plain text
+    loop {
+        let last = &client.message_history.last().expect("response").message;
+        let calls = last.tool_calls();
+        if calls.is_empty() {
+            break;
+        }
+        let results = calls.into_iter().map(run_tool).collect();
+        client
+            .send_chat_message(Message::from_tool_results(results))
+            .await?;
+    }
+
+    client.log_message_history();
+
+    Ok(())
+}
+
+fn run_tool(call: &ToolCall) -> ToolResult {
+    match call.name.as_str() {
+        "get_weather" => ToolResult::new(call, "22C and sunny".to_string()),
+        _ => ToolResult::error(call, format!("unknown tool {}", call.name)),
+    }
+}
+
+fn setup_tracing() {
+    let filter = EnvFilter::try_new("info,aipi=trace,reqwest=warn,hyper=warn").unwrap();
+    let json_layer = trace_fmt::layer().json().with_filter(filter);
+
+    tracing_subscriber::registry().with(json_layer).init();
+}
@@ -0,0 +1,724 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    iter::once,
+    ops::{Range, RangeInclusive},
+};
+
+use crate::{
+    client::{LlmClient, LlmClientError},
+    message::{Message, MessageBundle, Usage},
+};
+
+/// Mod purpose:
+/// Inputs too long for any context window (contracts, logs) are split into chunks & run through a client map-reduce style:
+/// every chunk is answered on its own, a bounded number in flight at once, then a reduce prompt combines the partial answers
+/// (in rounds, over groups of them first, when they don't all fit one prompt).
+/// Chunks are sent without the client's chat history & nothing is added to it, feed the answer into a chat if it belongs there.
+
+#[allow(clippy::empty_line_after_doc_comments)]
+/// ~4 chars per token, the same rough estimate budgets are projected with
+const CHARS_PER_TOKEN: usize = 4;
+
+/// How to split text into chunks, sizes are upper bounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chunker {
+    /// windows of `size` chars, each starting `overlap` chars before the previous one ended (overlap is capped below size);
+    /// a window ends after whitespace rather than mid-word where there's some in its back half
+    Characters { size: usize, overlap: usize },
+    /// as Characters, in estimated tokens
+    Tokens { size: usize, overlap: usize },
+    /// blank line separated paragraphs packed together up to `max_chars`, a longer paragraph is split as Characters
+    Paragraphs { max_chars: usize },
+    /// one chunk per markdown section (a heading & everything up to the next), longer sections are split as Paragraphs
+    MarkdownHeadings { max_chars: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// position among the chunks of its source text
+    pub index: usize,
+    pub text: String,
+    /// byte offset of the chunk's start in its source text
+    pub offset: usize,
+    /// heading of the markdown section the chunk is from, MarkdownHeadings only
+    pub heading: Option<String>,
+}
+
+// (offset, text, heading) before numbering
+type Piece<'a> = (usize, &'a str, Option<&'a str>);
+
+impl Chunker {
+    pub fn chunk(&self, text: &str) -> Vec<Chunk> {
+        let pieces = match *self {
+            Chunker::Characters { size, overlap } => split_chars(text, size, overlap),
+            Chunker::Tokens { size, overlap } => {
+                split_chars(text, size * CHARS_PER_TOKEN, overlap * CHARS_PER_TOKEN)
+            }
+            Chunker::Paragraphs { max_chars } => pack_paragraphs(text, max_chars, None),
+            Chunker::MarkdownHeadings { max_chars } => split_sections(text, max_chars),
+        };
+        pieces
+            .into_iter()
+            .filter(|(_, text, _)| !text.trim().is_empty())
+            .enumerate()
+            .map(|(index, (offset, text, heading))| Chunk {
+                index,
+                text: text.to_string(),
+                offset,
+                heading: heading.map(str::to_string),
+            })
+            .collect()
+    }
+}
+
+fn char_count(text: &str) -> usize {
+    text.chars().count()
+}
+
+fn split_chars(text: &str, size: usize, overlap: usize) -> Vec<Piece<'_>> {
+    let size = size.max(1);
+    let overlap = overlap.min(size - 1);
+    // byte offset of every char, & of the end
+    let bounds: Vec<usize> = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(once(text.len()))
+        .collect();
+    let chars = bounds.len() - 1;
+
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    while start < chars {
+        let mut end = (start + size).min(chars);
+        if end < chars
+            && let Some(after_space) = (start + size / 2 + 1..=end)
+                .rev()
+                .find(|&c| text[bounds[c - 1]..bounds[c]].starts_with(char::is_whitespace))
+        {
+            end = after_space;
+        }
+        pieces.push((bounds[start], &text[bounds[start]..bounds[end]], None));
+        if end == chars {
+            break;
+        }
+        // a window cut back at whitespace can be shorter than the overlap
+        start = end.saturating_sub(overlap).max(start + 1);
+    }
+    pieces
+}
+
+// byte spans of the non-blank runs of lines, trailing whitespace trimmed
+fn paragraphs(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        if line.trim().is_empty() {
+            spans.extend(current.take());
+        } else {
+            let end = start + line.trim_end().len();
+            current = Some((current.map_or(start, |(s, _)| s), end));
+        }
+    }
+    spans.extend(current);
+    spans
+}
+
+// adjacent paragraphs are kept as one slice of the source, so the blank lines between them survive
+fn pack_paragraphs<'a>(
+    text: &'a str,
+    max_chars: usize,
+    heading: Option<&'a str>,
+) -> Vec<Piece<'a>> {
+    let max_chars = max_chars.max(1);
+    let mut pieces = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    for (start, end) in paragraphs(text) {
+        if let Some((current_start, _)) = current
+            && char_count(&text[current_start..end]) <= max_chars
+        {
+            current = Some((current_start, end));
+            continue;
+        }
+        pieces.extend(current.take().map(|(s, e)| (s, &text[s..e], heading)));
+        if char_count(&text[start..end]) <= max_chars {
+            current = Some((start, end));
+        } else {
+            pieces.extend(
+                split_chars(&text[start..end], max_chars, 0)
+                    .into_iter()
+                    .map(|(offset, piece, _)| (start + offset, piece, heading)),
+            );
+        }
+    }
+    pieces.extend(current.map(|(s, e)| (s, &text[s..e], heading)));
+    pieces
+}
+
+fn split_sections(text: &str, max_chars: usize) -> Vec<Piece<'_>> {
+    // (offset, heading) of every section start, anything before the first heading is a section without one
+    let mut starts: Vec<(usize, Option<&str>)> = vec![(0, None)];
+    let mut in_fence = false;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        // a # in a code block is a comment, not a heading
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        } else if !in_fence && let Some(heading) = markdown_heading(trimmed) {
+            starts.push((offset, Some(heading)));
+        }
+        offset += line.len();
+    }
+
+    let ends = starts
+        .iter()
+        .skip(1)
+        .map(|(s, _)| *s)
+        .chain(once(text.len()));
+    starts
+        .iter()
+        .zip(ends)
+        .flat_map(|(&(start, heading), end)| {
+            let section = text[start..end].trim_end();
+            if char_count(section) <= max_chars {
+                vec![(start, section, heading)]
+            } else {
+                pack_paragraphs(section, max_chars, heading)
+                    .into_iter()
+                    .map(|(offset, piece, heading)| (start + offset, piece, heading))
+                    .collect()
+            }
+        })
+        .collect()
+}
+
+// "## Termination" -> "Termination"
+fn markdown_heading(line: &str) -> Option<&str> {
+    let level = line.len() - line.trim_start_matches('#').len();
+    let rest = &line[level..];
+    let is_heading =
+        (1..=6).contains(&level) && (rest.trim().is_empty() || rest.starts_with([' ', '\t']));
+    is_heading.then(|| rest.trim())
+}
+
+#[derive(Debug, Clone)]
+pub enum MapReduceError {
+    /// nothing to map
+    NoChunks,
+    /// more chunks failed than the run allows (or all of them did), the reduce wasn't sent
+    ChunksFailed(Vec<ChunkFailure>),
+    /// the up front budget check or the reduce request failed
+    Request(LlmClientError),
+}
+
+impl Display for MapReduceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{self:?}"))
+    }
+}
+
+impl Error for MapReduceError {}
+
+/// A chunk that didn't get an answer, after the client's retries
+#[derive(Debug, Clone)]
+pub struct ChunkFailure {
+    pub index: usize,
+    pub error: LlmClientError,
+}
+
+/// Reported as a run goes, chunks finish in whatever order their responses arrive
+#[derive(Debug, Clone, Copy)]
+pub enum MapReduceProgress<'a> {
+    ChunkDone {
+        index: usize,
+        completed: usize,
+        total: usize,
+    },
+    ChunkFailed {
+        index: usize,
+        completed: usize,
+        total: usize,
+        error: &'a LlmClientError,
+    },
+    /// every chunk is done & a reduce round is going out over this many answers,
+    /// there's a round per group of answers' results until they fit one reduce prompt
+    Reducing { partials: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct MapReduce {
+    /// asked of every chunk, e.g. "List every obligation the supplier takes on in this part of the contract."
+    pub map_prompt: String,
+    /// asked of the partial answers, e.g. "Merge these lists into one, dropping duplicates."
+    pub reduce_prompt: String,
+    /// chunk requests in flight at once
+    pub concurrency: usize,
+    /// chunks allowed to fail with the reduce still sent over the rest, 0 (the default) fails the run on any
+    pub max_failures: usize,
+    /// longest reduce prompt in chars, answers that don't fit one are reduced in groups of consecutive parts first.
+    /// None (the default) goes by the model's context window less its max_tokens, unbounded where that's unknown
+    pub max_reduce_chars: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MapReduceOutput {
+    /// the reduce response
+    pub answer: MessageBundle,
+    /// each chunk's response by chunk position, None where it failed
+    pub partials: Vec<Option<MessageBundle>>,
+    pub failures: Vec<ChunkFailure>,
+    /// summed over every chunk & the reduce
+    pub usage: Option<Usage>,
+}
+
+impl MapReduce {
+    pub fn new(map_prompt: String, reduce_prompt: String) -> MapReduce {
+        MapReduce {
+            map_prompt,
+            reduce_prompt,
+            concurrency: 4,
+            max_failures: 0,
+            max_reduce_chars: None,
+        }
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    pub fn with_max_failures(mut self, max_failures: usize) -> Self {
+        self.max_failures = max_failures;
+        self
+    }
+
+    pub fn with_max_reduce_chars(mut self, max_reduce_chars: usize) -> Self {
+        self.max_reduce_chars = Some(max_reduce_chars);
+        self
+    }
+
+    /// Answer the map prompt for every chunk then combine the answers with the reduce prompt, using `client`'s model & budget.
+    /// Chunks & the reduce are sent without the client's history and nothing is added to it
+    pub async fn run(
+        &self,
+        client: &mut LlmClient,
+        chunks: &[Chunk],
+        mut progress: impl FnMut(MapReduceProgress),
+    ) -> Result<MapReduceOutput, MapReduceError> {
+        if chunks.is_empty() {
+            return Err(MapReduceError::NoChunks);
+        }
+        let total = chunks.len();
+        let messages = chunks
+            .iter()
+            .map(|chunk| Message::from_user(self.map_message(chunk, total)))
+            .collect();
+
+        let mut completed = 0;
+        let results = client
+            .send_detached(messages, self.concurrency, |i, result| {
+                completed += 1;
+                let index = chunks[i].index;
+                progress(match result {
+                    Ok(_) => MapReduceProgress::ChunkDone {
+                        index,
+                        completed,
+                        total,
+                    },
+                    Err(error) => MapReduceProgress::ChunkFailed {
+                        index,
+                        completed,
+                        total,
+                        error,
+                    },
+                });
+            })
+            .await
+            .map_err(MapReduceError::Request)?;
+
+        let mut partials = Vec::with_capacity(total);
+        let mut failures = Vec::new();
+        for (chunk, result) in chunks.iter().zip(results) {
+            match result {
+                Ok(partial) => partials.push(Some(partial)),
+                Err(error) => {
+                    failures.push(ChunkFailure {
+                        index: chunk.index,
+                        error,
+                    });
+                    partials.push(None);
+                }
+            }
+        }
+        if failures.len() > self.max_failures || failures.len() == total {
+            return Err(MapReduceError::ChunksFailed(failures));
+        }
+
+        let max_chars = self.max_reduce_chars.or_else(|| {
+            let window = client.config.model.capabilities().context_window?;
+            Some(window.saturating_sub(client.config.max_tokens) * CHARS_PER_TOKEN)
+        });
+        let mut answers: Vec<Answer> = chunks
+            .iter()
+            .zip(&partials)
+            .map(|(chunk, partial)| Answer {
+                parts: chunk.index + 1..=chunk.index + 1,
+                text: partial.as_ref().map(|p| p.message.text()),
+            })
+            .collect();
+        let mut reduces = Vec::new();
+        let answer = loop {
+            progress(MapReduceProgress::Reducing {
+                partials: answers.len(),
+            });
+            let groups = self.reduce_groups(&answers, total, max_chars);
+            // a lone answer left over goes on to the next round as is
+            let messages = groups
+                .iter()
+                .filter(|group| groups.len() == 1 || group.len() > 1)
+                .map(|group| {
+                    Message::from_user(self.reduce_message(&answers[group.clone()], total))
+                })
+                .collect();
+            let results = client
+                .send_detached(messages, self.concurrency, |_, _| ())
+                .await
+                .map_err(MapReduceError::Request)?
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(MapReduceError::Request)?;
+            let mut results = results.into_iter();
+            if groups.len() == 1 {
+                break results.next().expect("one result per message");
+            }
+            answers = groups
+                .iter()
+                .map(|group| {
+                    if group.len() == 1 {
+                        return answers[group.start].clone();
+                    }
+                    let result = results.next().expect("one result per message");
+                    let answer = Answer {
+                        parts: *answers[group.start].parts.start()
+                            ..=*answers[group.end - 1].parts.end(),
+                        text: Some(result.message.text()),
+                    };
+                    reduces.push(result);
+                    answer
+                })
+                .collect();
+        };
+
+        let usage = partials
+            .iter()
+            .flatten()
+            .chain(&reduces)
+            .chain(once(&answer))
+            .filter_map(|b| b.usage().copied())
+            .reduce(|mut total, usage| {
+                total += usage;
+                total
+            });
+        Ok(MapReduceOutput {
+            answer,
+            partials,
+            failures,
+            usage,
+        })
+    }
+
+    fn map_message(&self, chunk: &Chunk, total: usize) -> String {
+        let section = chunk
+            .heading
+            .as_ref()
+            .map(|h| format!(", from the section \"{h}\""))
+            .unwrap_or_default();
+        format!(
+            "{}\n\n<document_part>\nPart {} of {}{}\n\n{}\n</document_part>",
+            self.map_prompt,
+            chunk.index + 1,
+            total,
+            section,
+            chunk.text
+        )
+    }
+
+    // consecutive answers packed into reduce prompts up to max_chars, all of them in one where they fit.
+    // Otherwise every group takes at least two so each round roughly halves what's left
+    fn reduce_groups(
+        &self,
+        answers: &[Answer],
+        total: usize,
+        max_chars: Option<usize>,
+    ) -> Vec<Range<usize>> {
+        let fits = |group: &Range<usize>| {
+            max_chars.is_none_or(|max| {
+                char_count(&self.reduce_message(&answers[group.clone()], total)) <= max
+            })
+        };
+        let all = 0..answers.len();
+        if fits(&all) {
+            return vec![all];
+        }
+        let mut groups: Vec<Range<usize>> = Vec::new();
+        let mut start = 0;
+        while start < answers.len() {
+            let mut end = (start + 2).min(answers.len());
+            while end < answers.len() && fits(&(start..end + 1)) {
+                end += 1;
+            }
+            groups.push(start..end);
+            start = end;
+        }
+        groups
+    }
+
+    // failed parts are listed as missing so the reduce doesn't read a gap as "nothing there"
+    fn reduce_message(&self, answers: &[Answer], total: usize) -> String {
+        let combined = if answers.iter().any(|a| a.parts.start() != a.parts.end()) {
+            " A range of part numbers is an answer already combined from those parts' answers."
+        } else {
+            ""
+        };
+        let answers: String = answers
+            .iter()
+            .map(|answer| {
+                let parts = if answer.parts.start() == answer.parts.end() {
+                    answer.parts.start().to_string()
+                } else {
+                    format!("{}-{}", answer.parts.start(), answer.parts.end())
+                };
+                match &answer.text {
+                    Some(text) => format!("[{parts}]\n{text}\n\n"),
+                    None => format!(
+                        "[{parts}]\n(missing, this part of the document could not be processed)\n\n"
+                    ),
+                }
+            })
+            .collect();
+        format!(
+            "{}\n\nThe document was too long to read at once, so it was split into {} parts & each was answered separately.{} \
+             Their answers, by part number:\n\n<partial_answers>\n{}</partial_answers>",
+            self.reduce_prompt, total, combined, answers
+        )
+    }
+}
+
+// an answer going into a reduce, for one part or a range already reduced together
+#[derive(Clone)]
+struct Answer {
+    parts: RangeInclusive<usize>,
+    text: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(chunks: &[Chunk]) -> Vec<&str> {
+        chunks.iter().map(|c| c.text.as_str()).collect()
+    }
+
+    // every chunk is where its offset says, within size, & together they cover the text (whitespace only chunks are dropped)
+    fn assert_windows(text: &str, chunks: &[Chunk], size: usize) {
+        let mut covered = 0;
+        for chunk in chunks {
+            assert_eq!(
+                &text[chunk.offset..chunk.offset + chunk.text.len()],
+                chunk.text
+            );
+            assert!(char_count(&chunk.text) <= size);
+            assert!(
+                chunk.offset <= covered || text[covered..chunk.offset].trim().is_empty(),
+                "gap before {}",
+                chunk.offset
+            );
+            covered = covered.max(chunk.offset + chunk.text.len());
+        }
+        assert_eq!(covered, text.len());
+    }
+
+    #[test]
+    fn characters_overlap_and_end_after_whitespace() {
+        let text = "one two three four five six seven";
+        let chunks = Chunker::Characters {
+            size: 12,
+            overlap: 4,
+        }
+        .chunk(text);
+        assert_windows(text, &chunks, 12);
+        assert_eq!(
+            texts(&chunks),
+            [
+                "one two ",
+                "two three ",
+                "ree four ",
+                "our five ",
+                "ive six ",
+                "six seven"
+            ]
+        );
+        assert_eq!(
+            chunks.iter().map(|c| c.index).collect::<Vec<_>>(),
+            [0, 1, 2, 3, 4, 5]
+        );
+    }
+
+    #[test]
+    fn overlap_longer_than_a_window_cut_back_at_whitespace() {
+        let text = format!("{} {}", "a".repeat(11), "x".repeat(200));
+        let chunks = Chunker::Characters {
+            size: 20,
+            overlap: 15,
+        }
+        .chunk(&text);
+        assert_windows(&text, &chunks, 20);
+        assert_eq!(chunks[0].text, format!("{} ", "a".repeat(11)));
+
+        let text = format!("{} {}", "a".repeat(1100), "x".repeat(5000));
+        let chunks = Chunker::Tokens {
+            size: 500,
+            overlap: 300,
+        }
+        .chunk(&text);
+        assert_windows(&text, &chunks, 500 * CHARS_PER_TOKEN);
+    }
+
+    #[test]
+    fn overlap_is_capped_below_size() {
+        let chunks = Chunker::Characters {
+            size: 4,
+            overlap: 9,
+        }
+        .chunk("abcdef");
+        assert_eq!(texts(&chunks), ["abcd", "bcde", "cdef"]);
+    }
+
+    #[test]
+    fn text_without_whitespace_is_cut_at_size() {
+        let text = "x".repeat(25);
+        let chunks = Chunker::Characters {
+            size: 10,
+            overlap: 3,
+        }
+        .chunk(&text);
+        assert_windows(&text, &chunks, 10);
+        assert_eq!(
+            chunks.iter().map(|c| c.offset).collect::<Vec<_>>(),
+            [0, 7, 14, 21]
+        );
+    }
+
+    #[test]
+    fn multibyte_text_is_split_on_char_boundaries() {
+        let text = "héllo wörld ünïcödé ✓✓✓✓✓✓✓✓ 日本語のテキスト";
+        for (size, overlap) in [(1, 0), (5, 2), (7, 6), (12, 3)] {
+            let chunks = Chunker::Characters { size, overlap }.chunk(text);
+            assert_windows(text, &chunks, size);
+        }
+        let chunks = Chunker::Tokens {
+            size: 2,
+            overlap: 1,
+        }
+        .chunk(text);
+        assert_windows(text, &chunks, 2 * CHARS_PER_TOKEN);
+    }
+
+    #[test]
+    fn paragraphs_are_packed_up_to_max() {
+        let text = "one\n\ntwo\n\n\nthree three three\n\nfour";
+        let chunks = Chunker::Paragraphs { max_chars: 10 }.chunk(text);
+        assert_eq!(
+            texts(&chunks),
+            ["one\n\ntwo", "three ", "three ", "three", "four"]
+        );
+        assert_eq!(chunks[1].offset, text.find("three").unwrap());
+    }
+
+    #[test]
+    fn markdown_sections_carry_their_heading() {
+        let text = "intro\n\n# Terms\nbody\n```\n# not a heading\n```\n## Termination\nends\n";
+        let chunks = Chunker::MarkdownHeadings { max_chars: 100 }.chunk(text);
+        assert_eq!(
+            texts(&chunks),
+            [
+                "intro",
+                "# Terms\nbody\n```\n# not a heading\n```",
+                "## Termination\nends"
+            ]
+        );
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|c| c.heading.as_deref())
+                .collect::<Vec<_>>(),
+            [None, Some("Terms"), Some("Termination")]
+        );
+    }
+
+    fn answers(count: usize, len: usize) -> Vec<Answer> {
+        (1..=count)
+            .map(|part| Answer {
+                parts: part..=part,
+                text: Some("x".repeat(len)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn answers_that_fit_are_reduced_at_once() {
+        let map_reduce = MapReduce::new("map".to_string(), "reduce".to_string());
+        let all = vec![Range { start: 0, end: 5 }];
+        assert_eq!(map_reduce.reduce_groups(&answers(5, 100), 5, None), all);
+        assert_eq!(
+            map_reduce.reduce_groups(&answers(5, 100), 5, Some(100_000)),
+            all
+        );
+    }
+
+    #[test]
+    fn answers_that_dont_fit_are_reduced_in_groups() {
+        let map_reduce = MapReduce::new("map".to_string(), "reduce".to_string());
+        let answers = answers(7, 100);
+        let three = char_count(&map_reduce.reduce_message(&answers[..3], 7));
+        assert_eq!(
+            map_reduce.reduce_groups(&answers, 7, Some(three)),
+            [0..3, 3..6, 6..7]
+        );
+        // every group takes two, however little fits, so rounds always shrink the answers
+        assert_eq!(
+            map_reduce.reduce_groups(&answers, 7, Some(10)),
+            [0..2, 2..4, 4..6, 6..7]
+        );
+    }
+
+    #[test]
+    fn reduce_message_labels_ranges_and_missing_parts() {
+        let map_reduce = MapReduce::new("map".to_string(), "reduce".to_string());
+        let answers = [
+            Answer {
+                parts: 1..=3,
+                text: Some("first".to_string()),
+            },
+            Answer {
+                parts: 4..=4,
+                text: None,
+            },
+        ];
+        let message = map_reduce.reduce_message(&answers, 4);
+        assert!(message.starts_with("reduce\n\n"));
+        assert!(message.contains("split into 4 parts"));
+        assert!(message.contains("already combined"));
+        assert!(message.contains("[1-3]\nfirst\n"));
+        assert!(message.contains("[4]\n(missing"));
+        assert!(
+            !map_reduce
+                .reduce_message(&answers[1..], 4)
+                .contains("already combined")
+        );
+    }
+}
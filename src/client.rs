@@ -1,22 +1,71 @@
-use std::{error::Error, fmt::Display};
+pub mod retry;
 
-use reqwest::{RequestBuilder, Response};
+use std::{
+    collections::VecDeque, error::Error, fmt::Display, path::Path, pin::Pin, sync::Arc,
+    time::Duration,
+};
+
+use futures::{Stream, StreamExt, future::join_all, stream};
+use reqwest::{RequestBuilder, Response, StatusCode};
 use secrecy::ExposeSecret;
-use tracing::{debug, info};
+use serde_json::Value;
+use tracing::{debug, info, warn};
 
 use crate::{
+    context::{ContextStrategy, Selection},
+    embedding::{self, Embeddings},
+    environment::get_api_key,
+    history::{HistoryError, read_history, write_history},
     message::{
-        Message, MessageBundle, MessageMetadata,
-        serde::{ModelRequestWrapper, ModelResponseWrapper},
+        Candidates, ContentPart, Message, MessageBundle, MessageMetadata, StopReason, StreamDelta,
+        Usage,
+        image::{Image, ImageSource},
+        serde::stream::{StreamFrame, into_lines},
     },
-    models::{Model, ModelConfig},
+    models::{Model, ModelConfig, Role},
+    pricing::get_pricing,
+    provider::{Builtin, Provider, ProviderResponse},
+    tool::ToolCall,
 };
 
+use retry::{RetryPolicy, is_retryable_error, is_retryable_status, retry_after};
+
+/// Sent in place of a user turn to have a response cut off at max_tokens picked back up, see with_auto_continue
+const CONTINUE_PROMPT: &str =
+    "Continue exactly where your last response was cut off, without repeating any of it.";
+
 #[derive(Debug, Clone)]
 pub enum LlmClientError {
     Request(String),
     ParseResponse(String),
     ExtractContent(String),
+    Unsupported(String),
+    Provider(ProviderError),
+    /// sending would (or might) take spend past the client's budget, all amounts in USD
+    BudgetExceeded {
+        budget: f64,
+        spent: f64,
+        projected: f64,
+    },
+    /// a candidate was committed that isn't in the set
+    CandidateOutOfRange {
+        index: usize,
+        count: usize,
+    },
+    /// commit_best had nothing to pick, the set was empty or every response scored NaN
+    NoCandidateScored {
+        count: usize,
+    },
+}
+
+impl LlmClientError {
+    /// Only provider errors are classified, anything else is assumed final
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            LlmClientError::Provider(e) => e.is_retryable(),
+            _ => false,
+        }
+    }
 }
 
 impl Display for LlmClientError {
@@ -27,20 +76,89 @@ impl Display for LlmClientError {
 
 impl Error for LlmClientError {}
 
+/// A non-success response from a provider, decoded from its error body where possible
+#[derive(Debug, Clone)]
+pub struct ProviderError {
+    pub status: StatusCode,
+    /// e.g. anthropic's "rate_limit_error", openai's "invalid_request_error", gemini's "RESOURCE_EXHAUSTED"
+    pub error_type: Option<String>,
+    /// e.g. openai's "context_length_exceeded"
+    pub code: Option<String>,
+    /// the provider's message, or the raw body when it isn't the documented error shape
+    pub message: String,
+    pub request_id: Option<String>,
+    pub retry_after: Option<Duration>,
+}
+
+impl ProviderError {
+    pub fn is_retryable(&self) -> bool {
+        // openai reports an exhausted quota as a 429, no amount of waiting fixes that
+        if self.code.as_deref() == Some("insufficient_quota") {
+            return false;
+        }
+        is_retryable_status(self.status) || self.error_type.as_deref() == Some("overloaded_error")
+    }
+
+    async fn from_response(
+        response: Response,
+        provider: &dyn Provider,
+        config: &ModelConfig,
+    ) -> Self {
+        let status = response.status();
+        let request_id = provider
+            .request_id_header(config)
+            .and_then(|h| response.headers().get(h))
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let retry_after = retry_after(response.headers());
+        let content = response.text().await.unwrap_or_default();
+
+        let (error_type, code, message) = match provider.decode_error(config, &content) {
+            Some(body) => (body.error_type, body.code, body.message),
+            None => (None, None, content),
+        };
+        ProviderError {
+            status,
+            error_type,
+            code,
+            message,
+            request_id,
+            retry_after,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LlmClient {
     pub message_history: Vec<MessageBundle>,
     pub config: ModelConfig,
+    pub retry_policy: RetryPolicy,
+    /// running total across every response this client has received
+    pub total_usage: Usage,
+    /// USD, running total across every response this client has received (responses from unpriced models count as 0)
+    pub total_cost: f64,
+    /// USD, refuse to send once projected spend would exceed this
+    pub budget: Option<f64>,
+    /// re-prompt up to this many times for the rest of a response cut off at max_tokens, 0 (the default) leaves it cut off
+    pub max_continuations: usize,
+    /// looked up by name for `Model::Custom`, see with_provider
+    pub providers: Vec<Arc<dyn Provider>>,
+    /// cuts down the history sent with each request, None (the default) sends all of it
+    pub context_strategy: Option<Arc<dyn ContextStrategy>>,
     pub(crate) client: reqwest::Client,
 }
 
 // pubs
 impl LlmClient {
     pub fn new(config: ModelConfig) -> LlmClient {
+        let mut history: Vec<MessageBundle> = Vec::new();
         match config.model {
             // openai doesn't have system at the message level, it comes as the first in the stream of messages
-            Model::ChatGpt(_) => {
-                let mut history: Vec<MessageBundle> = Vec::new();
+            // (ollama too, as a leading system message)
+            Model::ChatGpt(_)
+            | Model::OpenAiCompatible { .. }
+            | Model::AzureOpenAi { .. }
+            | Model::Ollama(_) => {
                 if let Some(sys_prompt) = config.system_prompt.as_ref() {
                     let system_message = MessageBundle::new(
                         Message::from_system(sys_prompt.clone()),
@@ -48,29 +166,68 @@ impl LlmClient {
                     );
                     history.push(system_message);
                 }
-                LlmClient {
-                    message_history: history,
-                    config,
-                    client: reqwest::Client::new(),
-                }
             }
-            // probably Gemini just slides up into this block
-            Model::Claude(_) => LlmClient {
-                message_history: Vec::new(),
-                config,
-                client: reqwest::Client::new(),
-            },
-            Model::Gemini(_) => todo!("gem"),
+            // gemini also takes its system prompt outside of the message stream
+            // & custom providers get the whole config to place it however their backend wants
+            Model::Claude(_) | Model::Gemini(_) | Model::Custom { .. } => (),
             #[cfg(feature = "dev-tools")]
             Model::None => panic!("dev tools only"),
         }
+        LlmClient::with_history(config, history)
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_budget(mut self, budget: f64) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Have responses that hit max_tokens continued & stitched into one, at most `max_continuations` extra requests each
+    /// (non-streamed messages only, responses that stopped mid tool call are left as they are)
+    pub fn with_auto_continue(mut self, max_continuations: usize) -> Self {
+        self.max_continuations = max_continuations;
+        self
+    }
+
+    /// Send each request with only the part of history `strategy` picks (e.g. context::KeepLastTurns),
+    /// message_history itself keeps every message
+    pub fn with_context_strategy(mut self, strategy: impl ContextStrategy + 'static) -> Self {
+        self.context_strategy = Some(Arc::new(strategy));
+        self
+    }
+
+    /// Register a provider for `Model::Custom { provider, .. }` configs to be routed to, by its `name()`
+    pub fn with_provider(mut self, provider: impl Provider + 'static) -> Self {
+        self.providers.push(Arc::new(provider));
+        self
+    }
+
+    /// USD cost of the responses currently in history, see total_cost for everything this client has spent
+    pub fn history_cost(&self) -> f64 {
+        self.message_history.iter().filter_map(|b| b.cost()).sum()
     }
 
     /// message with adding to client's message history (useful for multisequenced interactions)
     pub async fn send_chat_message(&mut self, message: Message) -> Result<(), LlmClientError> {
+        let provider = self.provider()?;
         let bundle = self.bundle_message(message);
-        let response = self.send_message_bundle(&bundle).await?;
-        let response_bundle = self.extract_response(response).await?;
+        let response = self
+            .send_message_bundle(
+                provider.as_ref(),
+                &self.config,
+                &self.message_history,
+                &bundle,
+                false,
+            )
+            .await?;
+        let response_bundle = self.extract_response(provider.as_ref(), response).await?;
+        let response_bundle = self
+            .continue_response(provider.as_ref(), &bundle, response_bundle)
+            .await?;
 
         // update history if response handling is successful
         self.message_history.push(bundle);
@@ -83,66 +240,818 @@ impl LlmClient {
         &mut self,
         message: Message,
     ) -> Result<MessageBundle, LlmClientError> {
+        let provider = self.provider()?;
         let bundle = self.bundle_message(message);
-        let response = self.send_message_bundle(&bundle).await?;
-        let response_bundle = self.extract_response(response).await?;
-        Ok(response_bundle)
+        let response = self
+            .send_message_bundle(
+                provider.as_ref(),
+                &self.config,
+                &self.message_history,
+                &bundle,
+                false,
+            )
+            .await?;
+        let response_bundle = self.extract_response(provider.as_ref(), response).await?;
+        self.continue_response(provider.as_ref(), &bundle, response_bundle)
+            .await
+    }
+
+    /// message with a streamed response; the client's message history is updated once the stream completes
+    /// (dropping the stream early or hitting an error leaves history untouched);
+    /// tool calls are assembled from their fragments & come last, as StreamDelta::ToolCall
+    pub async fn send_chat_message_stream(
+        &mut self,
+        message: Message,
+    ) -> Result<impl Stream<Item = Result<StreamDelta, LlmClientError>> + Unpin + '_, LlmClientError>
+    {
+        let provider = self.provider()?;
+        if !self.config.model.capabilities().streaming {
+            return Err(LlmClientError::Unsupported(format!(
+                "{} does not support streaming",
+                self.config.model
+            )));
+        }
+        if !provider.supports_streaming(&self.config) {
+            return Err(LlmClientError::Unsupported(format!(
+                "provider {:?} does not stream",
+                provider.name()
+            )));
+        }
+        let bundle = self.bundle_message(message);
+        let response = self
+            .send_message_bundle(
+                provider.as_ref(),
+                &self.config,
+                &self.message_history,
+                &bundle,
+                true,
+            )
+            .await?;
+        let state = ChatStream {
+            lines: Box::pin(into_lines(Box::pin(response.bytes_stream()))),
+            client: self,
+            provider,
+            bundle: Some(bundle),
+            pending: VecDeque::new(),
+            text: String::new(),
+            usage: None,
+            stop_reason: None,
+            tool_calls: Vec::new(),
+            partial_tool_calls: Vec::new(),
+            finished_tool_calls: VecDeque::new(),
+        };
+
+        Ok(Box::pin(stream::unfold(state, |mut state| async move {
+            loop {
+                // tool calls go out last, after the response is committed
+                if let Some(call) = state.finished_tool_calls.pop_front() {
+                    return Some((Ok(StreamDelta::ToolCall(call)), state));
+                }
+                // bundle is taken on completion or failure, either ends the stream
+                state.bundle.as_ref()?;
+                let frame = match state.pending.pop_front() {
+                    Some(frame) => Ok(frame),
+                    None => match state.lines.next().await {
+                        Some(Ok(line)) => {
+                            match state
+                                .provider
+                                .decode_stream_line(&state.client.config, &line)
+                            {
+                                Ok(frames) => {
+                                    state.pending.extend(frames);
+                                    continue;
+                                }
+                                Err(e) => Err(e),
+                            }
+                        }
+                        Some(Err(e)) => Err(LlmClientError::ExtractContent(e.to_string())),
+                        // a dropped connection mustn't pass for a finished answer
+                        None if state.provider.stream_has_end_marker(&state.client.config) => {
+                            Err(LlmClientError::ExtractContent(
+                                "stream ended before the response was complete".to_string(),
+                            ))
+                        }
+                        None => Ok(StreamFrame::Done),
+                    },
+                };
+
+                match frame {
+                    Ok(StreamFrame::Delta(StreamDelta::ToolCall(mut call)))
+                    | Ok(StreamFrame::ToolCall(mut call)) => {
+                        if call.id.is_empty() {
+                            call.id = ToolCall::positional_id(&call.name, state.tool_calls.len());
+                        }
+                        state.tool_calls.push(call);
+                    }
+                    Ok(StreamFrame::ToolCallDelta {
+                        index,
+                        id,
+                        name,
+                        arguments,
+                    }) => {
+                        state.push_tool_call_delta(index, id, name, &arguments);
+                    }
+                    Ok(StreamFrame::Delta(delta)) => {
+                        if let StreamDelta::Text(t) = &delta {
+                            state.text.push_str(t);
+                        }
+                        return Some((Ok(delta), state));
+                    }
+                    Ok(StreamFrame::Usage(usage)) => {
+                        state.usage.get_or_insert_default().merge_latest(usage);
+                    }
+                    Ok(StreamFrame::Stop(stop_reason)) => {
+                        state.stop_reason = Some(stop_reason);
+                    }
+                    Ok(StreamFrame::Done) => {
+                        if let Err(e) = state.assemble_tool_calls() {
+                            state.bundle = None;
+                            return Some((Err(e), state));
+                        }
+                        state.commit();
+                    }
+                    Ok(StreamFrame::Error(e)) => {
+                        state.bundle = None;
+                        return Some((Err(LlmClientError::Request(e)), state));
+                    }
+                    Err(e) => {
+                        state.bundle = None;
+                        return Some((Err(e), state));
+                    }
+                }
+            }
+        })))
+    }
+
+    /// n alternative responses to a message, history is untouched until one is committed (see commit_candidate & commit_best);
+    /// models that can't return several from one request get n parallel requests instead
+    /// (no auto-continue, every candidate is returned as it came back)
+    pub async fn send_candidates(
+        &mut self,
+        message: Message,
+        n: usize,
+    ) -> Result<Candidates, LlmClientError> {
+        let provider = self.provider()?;
+        let request = self.bundle_message(message);
+        let n = n.max(1);
+        // custom providers are handed one request at a time, whatever their model says
+        let native = !matches!(self.config.model, Model::Custom { .. })
+            && self.config.model.capabilities().candidates;
+        let (responses, usage) = match native {
+            true => self.request_candidates(&request, n).await?,
+            false => {
+                self.fan_out_candidates(provider.as_ref(), &request, n)
+                    .await?
+            }
+        };
+        Ok(Candidates {
+            request,
+            responses,
+            usage,
+        })
+    }
+
+    /// Add the message & the response at `index` to history, the rest are discarded.
+    /// The committed response carries the usage of the whole set, so history_cost counts every candidate paid for
+    pub fn commit_candidate(
+        &mut self,
+        mut candidates: Candidates,
+        index: usize,
+    ) -> Result<(), LlmClientError> {
+        let count = candidates.responses.len();
+        if index >= count {
+            return Err(LlmClientError::CandidateOutOfRange { index, count });
+        }
+        let mut response_bundle = candidates.responses.swap_remove(index);
+        if candidates.usage.is_some() {
+            response_bundle.metadata = response_bundle.metadata.with_usage(candidates.usage);
+        }
+        self.message_history.push(candidates.request);
+        self.message_history.push(response_bundle);
+        Ok(())
+    }
+
+    /// Commit the response `scorer` rates highest, see Candidates::best_by
+    pub fn commit_best(
+        &mut self,
+        candidates: Candidates,
+        scorer: impl FnMut(&MessageBundle) -> f64,
+    ) -> Result<(), LlmClientError> {
+        let Some(index) = candidates.best_by(scorer) else {
+            return Err(LlmClientError::NoCandidateScored {
+                count: candidates.responses.len(),
+            });
+        };
+        self.commit_candidate(candidates, index)
+    }
+
+    /// Embed each input with the config's model (openai, gemini or openai-compatible), one vector per input in input order;
+    /// `dimensions` shortens the vectors on models that support it. Inputs are sent in as few requests as the provider allows
+    pub async fn embed(
+        &mut self,
+        inputs: &[impl AsRef<str>],
+        dimensions: Option<usize>,
+    ) -> Result<Embeddings, LlmClientError> {
+        let model = &self.config.model;
+        if model.is_versionless() {
+            return Err(LlmClientError::Unsupported(format!(
+                "{model} needs a model version to send requests to"
+            )));
+        }
+        let (Some(url), Some(batch_size)) = (
+            model.to_embeddings_url(&self.config.base_url()),
+            embedding::max_batch(model),
+        ) else {
+            return Err(LlmClientError::Unsupported(format!(
+                "{model} has no embeddings api aipi speaks"
+            )));
+        };
+
+        let mut embeddings = Embeddings::default();
+        for batch in inputs.chunks(batch_size) {
+            let batch: Vec<&str> = batch.iter().map(AsRef::as_ref).collect();
+            let payload = embedding::encode_request(&self.config, &batch, dimensions);
+            debug!("Payload being sent {payload:?}");
+            // embeddings only bill input
+            let estimate = Usage {
+                input_tokens: payload.len().div_ceil(4) as u64,
+                ..Usage::default()
+            };
+            self.check_budget_for(&self.config.model, &estimate)?;
+
+            let response = self
+                .send_payload(&Builtin, &self.config, &url, payload)
+                .await?;
+            let content = response
+                .text()
+                .await
+                .map_err(|e| LlmClientError::ExtractContent(e.to_string()))?;
+            let batch = embedding::decode_response(&self.config.model, &content, batch.len())
+                .map_err(|e| LlmClientError::ParseResponse(e.to_string()))?;
+            self.record_usage(batch.usage.as_ref());
+            embeddings.append(batch);
+        }
+        Ok(embeddings)
     }
 
     pub fn log_message_history(&self) {
         info!("Message history: {:?}", self.message_history);
     }
+
+    /// write config & message history to disk; `.jsonl` paths get one message per line, anything else a json document
+    pub fn save_history(&self, path: impl AsRef<Path>) -> Result<(), HistoryError> {
+        write_history(path.as_ref(), &self.config, &self.message_history)
+    }
+
+    /// resume a conversation written by `save_history`, with the api key re-read from the environment
+    pub fn from_history(path: impl AsRef<Path>) -> Result<LlmClient, HistoryError> {
+        let (mut config, message_history) = read_history(path.as_ref())?;
+        config.token = get_api_key(&config.model).map_err(HistoryError::NoTokenSet)?;
+        Ok(LlmClient::with_history(config, message_history))
+    }
 }
 
 // private
 impl LlmClient {
+    // running totals start from whatever the history already spent
+    fn with_history(config: ModelConfig, message_history: Vec<MessageBundle>) -> LlmClient {
+        let total_usage = message_history
+            .iter()
+            .filter_map(|b| b.usage().copied())
+            .sum();
+        let total_cost = message_history.iter().filter_map(|b| b.cost()).sum();
+        LlmClient {
+            message_history,
+            config,
+            retry_policy: RetryPolicy::default(),
+            total_usage,
+            total_cost,
+            budget: None,
+            max_continuations: 0,
+            providers: Vec::new(),
+            context_strategy: None,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn provider(&self) -> Result<Arc<dyn Provider>, LlmClientError> {
+        match &self.config.model {
+            Model::Custom { provider, .. } => self
+                .providers
+                .iter()
+                .find(|p| p.name() == provider)
+                .cloned()
+                .ok_or_else(|| {
+                    LlmClientError::Unsupported(format!(
+                        "no provider registered as {provider:?}, add one with LlmClient::with_provider"
+                    ))
+                }),
+            _ => Ok(Arc::new(Builtin)),
+        }
+    }
+
     fn bundle_message(&self, message: Message) -> MessageBundle {
         MessageBundle::new(message, MessageMetadata::new(&self.config))
     }
 
+    // tokens are spent whether or not a response makes it into history, so adhoc messages & unpicked candidates count too
+    fn record_usage(&mut self, usage: Option<&Usage>) {
+        let Some(usage) = usage else {
+            return;
+        };
+        self.total_usage += *usage;
+        self.total_cost += get_pricing(&self.config.model)
+            .map(|p| p.cost(usage))
+            .unwrap_or_default();
+    }
+
+    // projection is deliberately pessimistic: ~4 bytes of payload per uncached input token & a full max_tokens response,
+    // for each of `requests` identical requests & each candidate they ask for
+    fn check_budget(
+        &self,
+        config: &ModelConfig,
+        payload: &str,
+        requests: usize,
+    ) -> Result<(), LlmClientError> {
+        let requests = requests as u64;
+        let estimate = Usage {
+            input_tokens: payload.len().div_ceil(4) as u64 * requests,
+            output_tokens: (config.max_tokens * config.candidates.unwrap_or(1)) as u64 * requests,
+            ..Usage::default()
+        };
+        self.check_budget_for(&config.model, &estimate)
+    }
+
+    fn check_budget_for(&self, model: &Model, estimate: &Usage) -> Result<(), LlmClientError> {
+        let Some(budget) = self.budget else {
+            return Ok(());
+        };
+        let pricing = get_pricing(model).ok_or_else(|| {
+            LlmClientError::Unsupported(format!(
+                "no pricing known for {model:?}, set one with pricing::set_pricing to enforce a budget"
+            ))
+        })?;
+
+        let projected = self.total_cost + pricing.cost(estimate);
+        if projected > budget {
+            return Err(LlmClientError::BudgetExceeded {
+                budget,
+                spent: self.total_cost,
+                projected,
+            });
+        }
+        Ok(())
+    }
+
     async fn send_message_bundle(
-        &mut self,
+        &self,
+        provider: &dyn Provider,
+        config: &ModelConfig,
+        history: &[MessageBundle],
         bundle: &MessageBundle,
+        stream: bool,
     ) -> Result<Response, LlmClientError> {
-        let wrapped_request = ModelRequestWrapper::new(bundle, self);
-        let payload = wrapped_request.to_payload();
+        let selection = self.select_context(history, bundle).await?;
+        let (history, bundle) = in_window(&selection, history, bundle);
+        self.send_window(provider, config, history, bundle, stream)
+            .await
+    }
+
+    // None when there's no context strategy, all of history goes
+    async fn select_context(
+        &self,
+        history: &[MessageBundle],
+        bundle: &MessageBundle,
+    ) -> Result<Option<Selection>, LlmClientError> {
+        match &self.context_strategy {
+            Some(strategy) => Ok(Some(strategy.select(history, bundle).await?)),
+            None => Ok(None),
+        }
+    }
+
+    // history & bundle go out as given, send_message_bundle applies the context strategy first
+    async fn send_window(
+        &self,
+        provider: &dyn Provider,
+        config: &ModelConfig,
+        history: &[MessageBundle],
+        bundle: &MessageBundle,
+        stream: bool,
+    ) -> Result<Response, LlmClientError> {
+        self.check_capabilities(history, bundle)?;
+        let payload = provider.encode_request(config, history, bundle, stream)?;
 
         debug!("Payload being sent {payload:?}");
+        self.check_budget(config, &payload, 1)?;
 
-        let response = self
-            .client
-            .post(self.config.model.to_target_url())
-            .with_model_headers(&self.config)
-            .body(payload)
-            .inspect(|rb| {
-                debug!("Inspecting built request before sending: {rb:?}");
-            })
-            .send()
-            .await
-            .map_err(|e| LlmClientError::Request(e.to_string()))?;
+        let url = provider.endpoint(config, stream);
+        self.send_payload(provider, config, &url, payload).await
+    }
 
-        Ok(response)
+    // retried per the client's policy, non-success statuses come back as errors
+    async fn send_payload(
+        &self,
+        provider: &dyn Provider,
+        config: &ModelConfig,
+        url: &str,
+        payload: String,
+    ) -> Result<Response, LlmClientError> {
+        let max_attempts = self.retry_policy.max_attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            debug!("Sending request, attempt {attempt} of {max_attempts}");
+            let request = provider.headers(config, self.client.post(url)).await?;
+            let result = request
+                .body(payload.clone())
+                .inspect(|rb| {
+                    debug!("Inspecting built request before sending: {rb:?}");
+                })
+                .send()
+                .await;
+
+            // non-success statuses are decoded here so the stream & non-stream paths never parse an error body as a success
+            let (failure, requested_delay) = match result {
+                Ok(response) if response.status().is_success() => break Ok(response),
+                Ok(response) => {
+                    let error = ProviderError::from_response(response, provider, config).await;
+                    debug!("Provider error: {error:?}");
+                    let retryable = error.is_retryable();
+                    let requested_delay = error.retry_after;
+                    let failure = LlmClientError::Provider(error);
+                    if !retryable {
+                        break Err(failure);
+                    }
+                    (failure, requested_delay)
+                }
+                Err(e) if is_retryable_error(&e) => (LlmClientError::Request(e.to_string()), None),
+                Err(e) => break Err(LlmClientError::Request(e.to_string())),
+            };
+
+            if attempt >= max_attempts {
+                warn!("Giving up after {attempt} attempts, last failure: {failure}");
+                break Err(failure);
+            }
+
+            let delay = self.retry_policy.delay_for(attempt, requested_delay);
+            warn!(
+                "Attempt {attempt} of {max_attempts} failed with {failure}, retrying in {}ms",
+                delay.as_millis()
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
     }
+
+    // catch content the model can't take before spending a request on it
+    fn check_capabilities(
+        &self,
+        history: &[MessageBundle],
+        bundle: &MessageBundle,
+    ) -> Result<(), LlmClientError> {
+        let mut outgoing = history.iter().chain(std::iter::once(bundle));
+        if !self.config.model.capabilities().vision && outgoing.any(|b| b.message.has_images()) {
+            return Err(LlmClientError::Unsupported(format!(
+                "{:?} does not accept image input",
+                self.config.model
+            )));
+        }
+        // ollama only takes images inline
+        let outgoing = history.iter().chain(std::iter::once(bundle));
+        if matches!(self.config.model, Model::Ollama(_))
+            && outgoing.flat_map(|b| &b.message.content).any(|p| {
+                matches!(
+                    p,
+                    ContentPart::Image(Image {
+                        source: ImageSource::Url { .. }
+                    })
+                )
+            })
+        {
+            return Err(LlmClientError::Unsupported(
+                "ollama only accepts inline images, load them with Image::from_path or Image::from_bytes"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+
     async fn extract_response(
         &mut self,
+        provider: &dyn Provider,
+        response: Response,
+    ) -> Result<MessageBundle, LlmClientError> {
+        let response_bundle = self.decode_response(provider, response).await?;
+        self.record_usage(response_bundle.usage());
+        Ok(response_bundle)
+    }
+
+    // usage is left for the caller to record, so responses can be decoded concurrently
+    async fn decode_response(
+        &self,
+        provider: &dyn Provider,
         response: Response,
     ) -> Result<MessageBundle, LlmClientError> {
         debug!("Unwrapping response: {response:?}");
-        let content = response
-            .text()
-            .await
-            .map_err(|e| LlmClientError::ExtractContent(e.to_string()))?;
+        if !response.status().is_success() {
+            let error = ProviderError::from_response(response, provider, &self.config).await;
+            return Err(LlmClientError::Provider(error));
+        }
+        let decoded = provider.decode_response(&self.config, response).await?;
+
+        debug!("Decoded response {decoded:?}");
+        Ok(self.response_bundle(decoded))
+    }
+
+    // build message from the decoded response
+    fn response_bundle(&self, decoded: ProviderResponse) -> MessageBundle {
+        let message_metadata = MessageMetadata::new(&self.config)
+            .with_usage(decoded.usage)
+            .with_stop_reason(decoded.stop_reason);
+        MessageBundle::new(decoded.message, message_metadata)
+    }
+
+    // one request asking for all n, the provider reports a single usage for the lot
+    async fn request_candidates(
+        &mut self,
+        request: &MessageBundle,
+        n: usize,
+    ) -> Result<(Vec<MessageBundle>, Option<Usage>), LlmClientError> {
+        let config = ModelConfig {
+            candidates: Some(n),
+            ..self.config.clone()
+        };
+        let response = self
+            .send_message_bundle(&Builtin, &config, &self.message_history, request, false)
+            .await?;
+        let (decoded, usage) = Builtin.decode_candidates(&config, response).await?;
+        self.record_usage(usage.as_ref());
+        let responses = decoded
+            .into_iter()
+            .map(|d| self.response_bundle(d))
+            .collect();
+        Ok((responses, usage))
+    }
+
+    // n identical requests in parallel, for providers that only ever answer with one
+    async fn fan_out_candidates(
+        &mut self,
+        provider: &dyn Provider,
+        request: &MessageBundle,
+        n: usize,
+    ) -> Result<(Vec<MessageBundle>, Option<Usage>), LlmClientError> {
+        // every candidate answers the same window, so the strategy (a summarizing request even) runs once
+        let selection = self.select_context(&self.message_history, request).await?;
+        let (history, request) = in_window(&selection, &self.message_history, request);
+        // each request checks against the same spend, so check for all of them up front
+        let payload = provider.encode_request(&self.config, history, request, false)?;
+        self.check_budget(&self.config, &payload, n)?;
+
+        let results = join_all((0..n).map(|_| async {
+            let response = self
+                .send_window(provider, &self.config, history, request, false)
+                .await?;
+            self.decode_response(provider, response).await
+        }))
+        .await;
+
+        // every response that made it back was paid for, even if a sibling failed
+        let mut responses = Vec::with_capacity(n);
+        let mut failure = None;
+        for result in results {
+            match result {
+                Ok(response_bundle) => {
+                    self.record_usage(response_bundle.usage());
+                    responses.push(response_bundle);
+                }
+                Err(e) => {
+                    failure.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(e) = failure {
+            return Err(e);
+        }
+        let usage =
+            responses
+                .iter()
+                .filter_map(|b| b.usage().copied())
+                .reduce(|mut total, usage| {
+                    total += usage;
+                    total
+                });
+        Ok((responses, usage))
+    }
+
+    /// Send each message on its own (no history beyond the system prompt, none added), up to `concurrency` in flight at once;
+    /// `on_done` hears about each one as it finishes, results come back in message order.
+    /// Only the up front budget check fails the whole batch, every other failure stays with its message
+    pub(crate) async fn send_detached(
+        &mut self,
+        messages: Vec<Message>,
+        concurrency: usize,
+        mut on_done: impl FnMut(usize, &Result<MessageBundle, LlmClientError>),
+    ) -> Result<Vec<Result<MessageBundle, LlmClientError>>, LlmClientError> {
+        let provider = self.provider()?;
+        let bundles: Vec<MessageBundle> = messages
+            .into_iter()
+            .map(|m| self.bundle_message(m))
+            .collect();
+        // openai style models carry the system prompt as the first message of history
+        let system_len = self
+            .message_history
+            .iter()
+            .take_while(|b| b.message.role == Role::System)
+            .count();
+        let system = &self.message_history[..system_len];
+
+        // each request checks against the same spend, so check for all of them up front
+        let mut estimate = Usage::default();
+        for bundle in &bundles {
+            let payload = provider.encode_request(&self.config, system, bundle, false)?;
+            estimate.input_tokens += payload.len().div_ceil(4) as u64;
+            estimate.output_tokens += self.config.max_tokens as u64;
+        }
+        self.check_budget_for(&self.config.model, &estimate)?;
+
+        let mut results: Vec<Option<Result<MessageBundle, LlmClientError>>> =
+            bundles.iter().map(|_| None).collect();
+        let client = &*self;
+        let mut in_flight = stream::iter(bundles.iter().enumerate())
+            .map(|(i, bundle)| {
+                let provider = provider.as_ref();
+                async move {
+                    let response = client
+                        .send_message_bundle(provider, &client.config, system, bundle, false)
+                        .await;
+                    let result = match response {
+                        Ok(response) => client.decode_response(provider, response).await,
+                        Err(e) => Err(e),
+                    };
+                    (i, result)
+                }
+            })
+            .buffer_unordered(concurrency.max(1));
+        while let Some((i, result)) = in_flight.next().await {
+            on_done(i, &result);
+            results[i] = Some(result);
+        }
+        drop(in_flight);
 
-        debug!("Deserializing and converting response content: {content:?}");
+        // every response that made it back was paid for
+        let results: Vec<Result<MessageBundle, LlmClientError>> =
+            results.into_iter().flatten().collect();
+        for response_bundle in results.iter().flatten() {
+            self.record_usage(response_bundle.usage());
+        }
+        Ok(results)
+    }
+
+    // the cut off response goes back as history, with each continuation stitched onto it
+    async fn continue_response(
+        &mut self,
+        provider: &dyn Provider,
+        bundle: &MessageBundle,
+        mut response_bundle: MessageBundle,
+    ) -> Result<MessageBundle, LlmClientError> {
+        let mut continuations = 0;
+        while continuations < self.max_continuations
+            && response_bundle.stop_reason() == Some(&StopReason::MaxTokens)
+            && response_bundle.message.tool_calls().is_empty()
+        {
+            continuations += 1;
+            debug!(
+                "Response hit max tokens, continuing ({continuations} of {})",
+                self.max_continuations
+            );
+            // pinned (in this copy only) so a context strategy can't drop the turn being continued
+            let mut answer = response_bundle.clone();
+            answer.pin();
+            let mut history = self.message_history.clone();
+            history.push(bundle.clone());
+            history.push(answer);
+            let prompt = self.bundle_message(Message::from_user(CONTINUE_PROMPT.to_string()));
+            let response = self
+                .send_message_bundle(provider, &self.config, &history, &prompt, false)
+                .await?;
+            let continuation = self.extract_response(provider, response).await?;
+            response_bundle.stitch(continuation);
+        }
+        Ok(response_bundle)
+    }
+}
 
-        let wrapped_response = ModelResponseWrapper::parse_new(content, &self.config)
-            .map_err(|e| LlmClientError::ParseResponse(e.to_string()))?;
+/// State threaded through a streamed chat response
+struct ChatStream<'a> {
+    client: &'a mut LlmClient,
+    provider: Arc<dyn Provider>,
+    lines: Pin<Box<dyn Stream<Item = Result<String, reqwest::Error>> + Send>>,
+    bundle: Option<MessageBundle>,
+    pending: VecDeque<StreamFrame>,
+    text: String,
+    usage: Option<Usage>,
+    stop_reason: Option<StopReason>,
+    tool_calls: Vec<ToolCall>,
+    /// (index, id, name, arguments so far) of calls still arriving in pieces
+    partial_tool_calls: Vec<(usize, String, String, String)>,
+    /// handed out once the response is committed
+    finished_tool_calls: VecDeque<ToolCall>,
+}
 
-        debug!("Wrapped response {wrapped_response:?}");
-        // build message from parsed & wrapped response
-        let message = Message::from(wrapped_response);
-        let message_metadata = MessageMetadata::new(&self.config);
-        Ok(MessageBundle::new(message, message_metadata))
+impl ChatStream<'_> {
+    fn push_tool_call_delta(
+        &mut self,
+        index: usize,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: &str,
+    ) {
+        let position = match self.partial_tool_calls.iter().position(|c| c.0 == index) {
+            Some(position) => position,
+            None => {
+                self.partial_tool_calls
+                    .push((index, String::new(), String::new(), String::new()));
+                self.partial_tool_calls.len() - 1
+            }
+        };
+        let call = &mut self.partial_tool_calls[position];
+        if let Some(id) = id {
+            call.1 = id;
+        }
+        if let Some(name) = name {
+            call.2 = name;
+        }
+        call.3.push_str(arguments);
+    }
+
+    // the json of a call's arguments is only whole once the response is
+    fn assemble_tool_calls(&mut self) -> Result<(), LlmClientError> {
+        let mut partials = std::mem::take(&mut self.partial_tool_calls);
+        partials.sort_by_key(|c| c.0);
+        for (_, id, name, arguments) in partials {
+            let arguments = match arguments.trim() {
+                "" => Value::Object(Default::default()),
+                json => serde_json::from_str(json).map_err(|e| {
+                    LlmClientError::ParseResponse(format!(
+                        "arguments of streamed tool call {name:?}: {e}"
+                    ))
+                })?,
+            };
+            self.tool_calls.push(ToolCall {
+                id,
+                name,
+                arguments,
+            });
+        }
+        self.finished_tool_calls = self.tool_calls.iter().cloned().collect();
+        Ok(())
+    }
+
+    // mirrors send_chat_message, history only changes once the full response is in hand
+    fn commit(&mut self) {
+        if let Some(bundle) = self.bundle.take() {
+            let text = std::mem::take(&mut self.text);
+            // gemini sends its calls & the plain STOP ending the turn in separate chunks
+            let stop_reason = match self.stop_reason.take() {
+                Some(StopReason::EndTurn) | None if !self.tool_calls.is_empty() => {
+                    Some(StopReason::ToolUse)
+                }
+                stop_reason => stop_reason,
+            };
+            let metadata = MessageMetadata::new(&self.client.config)
+                .with_usage(self.usage)
+                .with_stop_reason(stop_reason);
+            let message = match self.tool_calls.is_empty() {
+                true => Message::from_ai(text),
+                false => Message::new(
+                    Role::Ai,
+                    Some(text)
+                        .filter(|t| !t.is_empty())
+                        .map(ContentPart::Text)
+                        .into_iter()
+                        .chain(self.tool_calls.drain(..).map(ContentPart::ToolUse))
+                        .collect(),
+                ),
+            };
+            let response_bundle = MessageBundle::new(message, metadata);
+            self.client.record_usage(response_bundle.usage());
+            self.client.message_history.push(bundle);
+            self.client.message_history.push(response_bundle);
+        }
+    }
+}
+
+// what goes out once a context strategy's selection (if any) is applied
+fn in_window<'a>(
+    selection: &'a Option<Selection>,
+    history: &'a [MessageBundle],
+    bundle: &'a MessageBundle,
+) -> (&'a [MessageBundle], &'a MessageBundle) {
+    match selection {
+        Some(selection) => (
+            selection.history.as_slice(),
+            selection.next.as_ref().unwrap_or(bundle),
+        ),
+        None => (history, bundle),
     }
 }
 
@@ -161,7 +1070,22 @@ impl WithModelHeaders for RequestBuilder {
             Model::ChatGpt(_) => self
                 .bearer_auth(config.token.expose_secret())
                 .header("content-type", "application/json"),
-            Model::Gemini(_) => todo!("gem"),
+            // azure keys go in their own header rather than as a bearer token
+            Model::AzureOpenAi { .. } => self
+                .header("api-key", config.token.expose_secret())
+                .header("content-type", "application/json"),
+            Model::OpenAiCompatible { .. } => match config.token.expose_secret() {
+                "" => self.header("content-type", "application/json"),
+                token => self
+                    .bearer_auth(token)
+                    .header("content-type", "application/json"),
+            },
+            Model::Ollama(_) => self.header("content-type", "application/json"),
+            Model::Gemini(_) => self
+                .header("x-goog-api-key", config.token.expose_secret())
+                .header("content-type", "application/json"),
+            // auth is up to the custom provider's own headers
+            Model::Custom { .. } => self.header("content-type", "application/json"),
             #[cfg(feature = "dev-tools")]
             Model::None => panic!("dev-tools only"),
         }
@@ -172,3 +1096,139 @@ impl WithModelHeaders for RequestBuilder {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::tests::config;
+
+    fn candidates(config: &ModelConfig, usage: Option<Usage>) -> Candidates {
+        let bundle = |message| MessageBundle::new(message, MessageMetadata::new(config));
+        Candidates {
+            request: bundle(Message::from_user("Hi".to_string())),
+            responses: vec![
+                bundle(Message::from_ai("short".to_string())),
+                bundle(Message::from_ai("longest".to_string())),
+            ],
+            usage,
+        }
+    }
+
+    #[test]
+    fn commit_best_commits_the_top_scorer_with_the_set_usage() {
+        let mut client = LlmClient::new(config(Model::Ollama("llama3.2".to_string())));
+        let usage = Usage {
+            input_tokens: 10,
+            output_tokens: 40,
+            ..Usage::default()
+        };
+        let candidates = candidates(&client.config, Some(usage));
+
+        client
+            .commit_best(candidates, |b| b.message.text().len() as f64)
+            .unwrap();
+
+        assert_eq!(client.message_history.len(), 2);
+        assert_eq!(client.message_history[1].message.text(), "longest");
+        assert_eq!(client.message_history[1].usage(), Some(&usage));
+    }
+
+    #[test]
+    fn commit_best_without_a_score_is_an_error() {
+        let mut client = LlmClient::new(config(Model::Ollama("llama3.2".to_string())));
+        let candidates = candidates(&client.config, None);
+
+        let result = client.commit_best(candidates, |_| f64::NAN);
+
+        assert!(matches!(
+            result,
+            Err(LlmClientError::NoCandidateScored { count: 2 })
+        ));
+        assert!(client.message_history.is_empty());
+    }
+
+    #[test]
+    fn custom_models_get_no_builtin_auth() {
+        let mut config = config(Model::Custom {
+            provider: "acme".to_string(),
+            model_id: "rocket-1".to_string(),
+        });
+        config.token = secrecy::SecretString::from("secret");
+
+        let request = reqwest::Client::new()
+            .post("http://localhost/chat")
+            .with_model_headers(&config)
+            .build()
+            .unwrap();
+
+        let headers: Vec<&str> = request.headers().keys().map(|k| k.as_str()).collect();
+        assert_eq!(headers, ["content-type"]);
+    }
+
+    #[test]
+    fn azure_and_openai_compatible_auth_headers() {
+        let headers = |model: Model, token: &str| {
+            let mut config = config(model);
+            config.token = secrecy::SecretString::from(token.to_string());
+            let request = reqwest::Client::new()
+                .post("http://localhost/chat")
+                .with_model_headers(&config)
+                .build()
+                .unwrap();
+            let mut headers: Vec<(String, String)> = request
+                .headers()
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_str().unwrap().to_string()))
+                .collect();
+            headers.sort();
+            headers
+        };
+        let header = |name: &str, value: &str| (name.to_string(), value.to_string());
+        let compatible = Model::OpenAiCompatible {
+            model_id: "qwen3".to_string(),
+            base_url: "http://localhost:8000/v1".to_string(),
+        };
+
+        assert_eq!(
+            headers(
+                Model::AzureOpenAi {
+                    deployment: "gpt-4o-prod".to_string()
+                },
+                "azure-key"
+            ),
+            [
+                header("api-key", "azure-key"),
+                header("content-type", "application/json")
+            ]
+        );
+        assert_eq!(
+            headers(compatible.clone(), "local-key"),
+            [
+                header("authorization", "Bearer local-key"),
+                header("content-type", "application/json")
+            ]
+        );
+        // servers running without auth get no authorization header at all
+        assert_eq!(
+            headers(compatible, ""),
+            [header("content-type", "application/json")]
+        );
+    }
+
+    #[test]
+    fn exhausted_quota_is_not_retried_but_overload_is() {
+        let error = |status: u16, error_type: Option<&str>, code: Option<&str>| ProviderError {
+            status: StatusCode::from_u16(status).unwrap(),
+            error_type: error_type.map(str::to_string),
+            code: code.map(str::to_string),
+            message: String::new(),
+            request_id: None,
+            retry_after: None,
+        };
+        assert!(error(429, Some("rate_limit_error"), None).is_retryable());
+        assert!(!error(429, Some("insufficient_quota"), Some("insufficient_quota")).is_retryable());
+        // anthropic's overloaded_error comes as a 529 but is retried whatever the status
+        assert!(error(400, Some("overloaded_error"), None).is_retryable());
+        assert!(!error(400, Some("invalid_request_error"), None).is_retryable());
+    }
+}
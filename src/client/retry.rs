@@ -0,0 +1,199 @@
+use std::time::Duration;
+
+use reqwest::{StatusCode, header::HeaderMap};
+
+/// Mod purpose:
+/// Decide whether & when a failed request to a provider is retried.
+/// Only conditions where the provider never produced a response for us are retried:
+/// rate limiting, overload, timeouts the server reports (408), server side failures & connections that failed before the request went out.
+/// Once a request has been sent, a client side timeout or dropped connection may hide a generation that is running & billed,
+/// so those are surfaced as is, along with everything else.
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// total attempts including the first, 1 disables retries
+    pub max_attempts: u32,
+    /// delay before the first retry, doubled for each one after
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// randomize each delay between half & all of its computed value, so parallel clients don't retry in lockstep
+    pub jitter: bool,
+    /// prefer the provider's retry-after header over the computed delay when it's present (still capped at max_delay)
+    pub honor_retry_after: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+            honor_retry_after: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// delay before retrying after the given (1-based) failed attempt
+    pub(crate) fn delay_for(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if self.honor_retry_after
+            && let Some(requested) = retry_after
+        {
+            return requested.min(self.max_delay);
+        }
+
+        let exponent = attempt.saturating_sub(1).min(16);
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        match self.jitter {
+            true => delay / 2 + delay.mul_f64(fastrand::f64() / 2.0),
+            false => delay,
+        }
+    }
+}
+
+/// 529 is anthropic's "overloaded"
+pub(crate) fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 408 | 429 | 500 | 502 | 503 | 504 | 529)
+}
+
+// connect failures (refused, dns, tls handshake) mean the request was never sent
+pub(crate) fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_connect()
+}
+
+/// Reads `retry-after-ms` (openai) or `retry-after` as either seconds or an http date
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(ms) = headers
+        .get("retry-after-ms")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .filter(|ms| ms.is_finite())
+    {
+        return Duration::try_from_secs_f64(ms.max(0.0) / 1000.0).ok();
+    }
+
+    let value = headers.get("retry-after")?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<f64>()
+        && seconds.is_finite()
+    {
+        return Duration::try_from_secs_f64(seconds.max(0.0)).ok();
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (at.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            jitter: false,
+            ..RetryPolicy::default()
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn delay_doubles_per_attempt_up_to_max() {
+        let policy = policy();
+        assert_eq!(policy.delay_for(1, None), Duration::from_millis(500));
+        assert_eq!(policy.delay_for(2, None), Duration::from_secs(1));
+        assert_eq!(policy.delay_for(3, None), Duration::from_secs(2));
+        assert_eq!(policy.delay_for(20, None), policy.max_delay);
+        assert_eq!(policy.delay_for(u32::MAX, None), policy.max_delay);
+    }
+
+    #[test]
+    fn jittered_delay_stays_within_half_to_full() {
+        let policy = RetryPolicy::default();
+        for _ in 0..100 {
+            let delay = policy.delay_for(3, None);
+            assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_secs(2));
+        }
+    }
+
+    #[test]
+    fn retry_after_is_honored_but_capped_at_max_delay() {
+        let policy = policy();
+        let requested = Some(Duration::from_secs(2));
+        assert_eq!(policy.delay_for(1, requested), Duration::from_secs(2));
+        assert_eq!(
+            policy.delay_for(1, Some(Duration::from_secs(3600))),
+            policy.max_delay
+        );
+
+        let ignoring = RetryPolicy {
+            honor_retry_after: false,
+            ..policy
+        };
+        assert_eq!(ignoring.delay_for(1, requested), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn retry_after_parses_seconds_millis_and_dates() {
+        assert_eq!(
+            retry_after(&headers(&[("retry-after", "3")])),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            retry_after(&headers(&[("retry-after", "1.5")])),
+            Some(Duration::from_millis(1500))
+        );
+        // openai's millisecond header wins
+        assert_eq!(
+            retry_after(&headers(&[("retry-after-ms", "250"), ("retry-after", "3")])),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(
+            retry_after(&headers(&[("retry-after", "-4")])),
+            Some(Duration::ZERO)
+        );
+
+        let at = (chrono::Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        let until = retry_after(&headers(&[("retry-after", &at)])).unwrap();
+        assert!(until > Duration::from_secs(25) && until <= Duration::from_secs(30));
+        // a date already past has nothing to wait for
+        let past = (chrono::Utc::now() - chrono::Duration::seconds(30)).to_rfc2822();
+        assert_eq!(retry_after(&headers(&[("retry-after", &past)])), None);
+    }
+
+    #[test]
+    fn unusable_retry_after_is_ignored() {
+        assert_eq!(retry_after(&headers(&[])), None);
+        assert_eq!(retry_after(&headers(&[("retry-after", "soon")])), None);
+        assert_eq!(retry_after(&headers(&[("retry-after", "inf")])), None);
+        assert_eq!(retry_after(&headers(&[("retry-after-ms", "NaN")])), None);
+    }
+
+    #[test]
+    fn only_timeouts_throttling_and_server_failures_are_retryable_statuses() {
+        for status in [408, 429, 500, 502, 503, 504, 529] {
+            assert!(is_retryable_status(StatusCode::from_u16(status).unwrap()));
+        }
+        for status in [400, 401, 403, 404, 422] {
+            assert!(!is_retryable_status(StatusCode::from_u16(status).unwrap()));
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use std::{fmt::Debug, ops::Range, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use crate::{
+    client::{LlmClient, LlmClientError},
+    message::{ContentPart, Message, MessageBundle, MessageTimestamp},
+    models::Role,
+};
+
+/// Mod purpose:
+/// Long chats outgrow the model's context window, so a client can be given a strategy that picks what part of its history
+/// goes out with each request. The client's message_history always keeps everything; only the payload is cut down.
+/// Strategies work in whole turns (a user message & everything up to the next), so tool calls stay paired with their results,
+/// and never drop the leading system message, pinned turns or a turn still in progress.
+
+#[allow(clippy::empty_line_after_doc_comments)]
+/// Sent to the summarizer model, followed by the previous summary (if any) & the turns being folded into it
+const SUMMARY_PROMPT: &str = "Summarize the conversation below for the assistant continuing it. \
+Keep facts, decisions, open questions & anything the user asked to have remembered; drop pleasantries. Be concise.";
+
+/// Picks the history sent with each request, see LlmClient::with_context_strategy
+#[async_trait]
+pub trait ContextStrategy: Debug + Send + Sync {
+    /// History to send ahead of `next`, & `next` itself where the strategy amends it
+    async fn select(
+        &self,
+        history: &[MessageBundle],
+        next: &MessageBundle,
+    ) -> Result<Selection, LlmClientError>;
+}
+
+/// What a strategy sends with a request, only the payload changes & the client's history keeps `next` as it was
+#[derive(Debug, Clone)]
+pub struct Selection {
+    /// in order
+    pub history: Vec<MessageBundle>,
+    /// sent in place of `next`, None sends it as is
+    pub next: Option<MessageBundle>,
+}
+
+impl From<Vec<MessageBundle>> for Selection {
+    fn from(history: Vec<MessageBundle>) -> Self {
+        Selection {
+            history,
+            next: None,
+        }
+    }
+}
+
+/// History split at its user messages
+struct Turns<'a> {
+    history: &'a [MessageBundle],
+    /// anything before the first user message, i.e. an openai style system prompt
+    lead: Range<usize>,
+    turns: Vec<Range<usize>>,
+    /// `next` carries on the last turn (tool results, say) rather than starting one
+    in_progress: bool,
+}
+
+impl<'a> Turns<'a> {
+    fn new(history: &'a [MessageBundle], next: &MessageBundle) -> Self {
+        let starts: Vec<usize> = history
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.message.role == Role::User)
+            .map(|(i, _)| i)
+            .collect();
+        let lead = 0..starts.first().copied().unwrap_or(history.len());
+        let ends = starts.iter().skip(1).copied().chain(Some(history.len()));
+        let turns = starts.iter().zip(ends).map(|(&s, e)| s..e).collect();
+        Turns {
+            history,
+            lead,
+            turns,
+            in_progress: next.message.role != Role::User,
+        }
+    }
+
+    /// Turns kept whatever the strategy
+    fn required(&self, turn: usize) -> bool {
+        (self.in_progress && turn + 1 == self.turns.len())
+            || self.history[self.turns[turn].clone()]
+                .iter()
+                .any(|b| b.metadata.pinned())
+    }
+
+    fn assemble(&self, keep: &[bool]) -> Vec<MessageBundle> {
+        let kept = self
+            .turns
+            .iter()
+            .zip(keep)
+            .filter(|(_, keep)| **keep)
+            .flat_map(|(turn, _)| &self.history[turn.clone()]);
+        self.history[self.lead.clone()]
+            .iter()
+            .chain(kept)
+            .cloned()
+            .collect()
+    }
+}
+
+/// ~4 bytes of json per token, the same rough estimate the client's budget check uses
+fn estimate_tokens(bundles: &[MessageBundle]) -> usize {
+    bundles
+        .iter()
+        .map(|b| {
+            serde_json::to_string(&b.message)
+                .map(|json| json.len().div_ceil(4))
+                .unwrap_or_default()
+        })
+        .sum()
+}
+
+/// Send only the last N turns
+#[derive(Debug, Clone, Copy)]
+pub struct KeepLastTurns(pub usize);
+
+#[async_trait]
+impl ContextStrategy for KeepLastTurns {
+    async fn select(
+        &self,
+        history: &[MessageBundle],
+        next: &MessageBundle,
+    ) -> Result<Selection, LlmClientError> {
+        let turns = Turns::new(history, next);
+        let recent = turns.turns.len().saturating_sub(self.0);
+        let keep: Vec<bool> = (0..turns.turns.len())
+            .map(|i| i >= recent || turns.required(i))
+            .collect();
+        Ok(turns.assemble(&keep).into())
+    }
+}
+
+/// Send as many of the most recent turns as fit in an estimated token budget (say the model's context window),
+/// after setting aside room for `next` & a response of the config's max_tokens.
+/// The system message, pinned turns & a turn in progress are sent regardless & count against the budget
+#[derive(Debug, Clone, Copy)]
+pub struct TokenBudget(pub usize);
+
+#[async_trait]
+impl ContextStrategy for TokenBudget {
+    async fn select(
+        &self,
+        history: &[MessageBundle],
+        next: &MessageBundle,
+    ) -> Result<Selection, LlmClientError> {
+        let turns = Turns::new(history, next);
+        let mut keep: Vec<bool> = (0..turns.turns.len()).map(|i| turns.required(i)).collect();
+        let mut spent = estimate_tokens(std::slice::from_ref(next))
+            + next.metadata.config().max_tokens
+            + estimate_tokens(&history[turns.lead.clone()])
+            + turns
+                .turns
+                .iter()
+                .zip(&keep)
+                .filter(|(_, keep)| **keep)
+                .map(|(turn, _)| estimate_tokens(&history[turn.clone()]))
+                .sum::<usize>();
+
+        // newest first, stopping at the first that doesn't fit so what's sent stays a contiguous stretch of the chat
+        for (turn, keep) in turns.turns.iter().zip(keep.iter_mut()).rev() {
+            if *keep {
+                continue;
+            }
+            let cost = estimate_tokens(&history[turn.clone()]);
+            if spent + cost > self.0 {
+                break;
+            }
+            spent += cost;
+            *keep = true;
+        }
+        Ok(turns.assemble(&keep).into())
+    }
+}
+
+/// Send the last N turns, with everything older folded into a running summary written by a secondary (cheaper) model.
+/// The summary is only extended when more turns age out, so the summarizer is called once per turn at most.
+/// Its spend goes on the summarizer client, not the chat client; clones share the summarizer & summary
+#[derive(Debug, Clone)]
+pub struct Summarize {
+    pub keep_last_turns: usize,
+    state: Arc<Mutex<SummaryState>>,
+}
+
+#[derive(Debug)]
+struct SummaryState {
+    summarizer: LlmClient,
+    /// history up to here is in the summary
+    covered: usize,
+    /// of the last message covered, to tell the history it was cut from apart from a replacement
+    covered_last: Option<MessageTimestamp>,
+    summary: Option<String>,
+}
+
+impl Summarize {
+    pub fn new(summarizer: LlmClient, keep_last_turns: usize) -> Summarize {
+        Summarize {
+            keep_last_turns,
+            state: Arc::new(Mutex::new(SummaryState {
+                summarizer,
+                covered: 0,
+                covered_last: None,
+                summary: None,
+            })),
+        }
+    }
+
+    /// The summary of the turns no longer sent, None until some have aged out
+    pub async fn summary(&self) -> Option<String> {
+        self.state.lock().await.summary.clone()
+    }
+
+    /// USD spent on summaries so far
+    pub async fn total_cost(&self) -> f64 {
+        self.state.lock().await.summarizer.total_cost
+    }
+}
+
+#[async_trait]
+impl ContextStrategy for Summarize {
+    async fn select(
+        &self,
+        history: &[MessageBundle],
+        next: &MessageBundle,
+    ) -> Result<Selection, LlmClientError> {
+        let turns = Turns::new(history, next);
+        let recent = turns.turns.len().saturating_sub(self.keep_last_turns);
+        let keep: Vec<bool> = (0..turns.turns.len())
+            .map(|i| i >= recent || turns.required(i))
+            .collect();
+        let dropped: Vec<&Range<usize>> = turns
+            .turns
+            .iter()
+            .zip(&keep)
+            .filter(|(_, keep)| !**keep)
+            .map(|(turn, _)| turn)
+            .collect();
+        let Some(last_dropped) = dropped.last() else {
+            return Ok(turns.assemble(&keep).into());
+        };
+
+        let mut state = self.state.lock().await;
+        // the history was cut back (or swapped) since the last summary
+        let boundary = state
+            .covered
+            .checked_sub(1)
+            .and_then(|last| history.get(last))
+            .map(|b| b.metadata.timestamp());
+        if boundary != state.covered_last.as_ref() {
+            state.covered = 0;
+            state.covered_last = None;
+            state.summary = None;
+        }
+        let unsummarized: Vec<&MessageBundle> = dropped
+            .iter()
+            .filter(|turn| turn.start >= state.covered)
+            .flat_map(|turn| &history[(*turn).clone()])
+            .collect();
+        if !unsummarized.is_empty() {
+            let previous = state
+                .summary
+                .as_ref()
+                .map(|s| format!("<previous_summary>\n{s}\n</previous_summary>\n\n"))
+                .unwrap_or_default();
+            let prompt = format!(
+                "{SUMMARY_PROMPT}\n\n{previous}<conversation>\n{}</conversation>",
+                transcript(&unsummarized)
+            );
+            let response = state
+                .summarizer
+                .send_adhoc_message(Message::from_user(prompt))
+                .await?;
+            state.summary = Some(response.message.text());
+            state.covered = last_dropped.end;
+            state.covered_last = Some(history[last_dropped.end - 1].metadata.timestamp().clone());
+        }
+        let summary = format!(
+            "Summary of the earlier conversation:\n{}\n\n",
+            state.summary.as_deref().unwrap_or_default()
+        );
+        drop(state);
+
+        // the summary goes at the front of the first user message after the turns it covers, `next` if none is kept
+        let mut window = turns.assemble(&keep);
+        let first_after = turns.lead.len()
+            + turns
+                .turns
+                .iter()
+                .zip(&keep)
+                .filter(|(turn, keep)| **keep && turn.start < last_dropped.start)
+                .map(|(turn, _)| turn.len())
+                .sum::<usize>();
+        let mut amended = None;
+        let message = match window.get_mut(first_after) {
+            Some(bundle) => &mut bundle.message,
+            None => &mut amended.insert(next.clone()).message,
+        };
+        match message.content.first_mut() {
+            Some(ContentPart::Text(text)) => text.insert_str(0, &summary),
+            _ => message.content.insert(0, ContentPart::Text(summary)),
+        }
+        Ok(Selection {
+            history: window,
+            next: amended,
+        })
+    }
+}
+
+// plain text rendering for the summarizer, tool traffic included so its outcomes survive
+fn transcript(bundles: &[&MessageBundle]) -> String {
+    bundles
+        .iter()
+        .map(|b| {
+            let speaker = match b.message.role {
+                Role::User => "user",
+                Role::Ai => "assistant",
+                Role::System => "system",
+                Role::Tool => "tool",
+            };
+            let content: Vec<String> = b
+                .message
+                .content
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text(text) => Some(text.clone()),
+                    ContentPart::ToolUse(call) => {
+                        Some(format!("[called {}({})]", call.name, call.arguments))
+                    }
+                    ContentPart::ToolResult(result) => {
+                        Some(format!("[{} returned: {}]", result.name, result.content))
+                    }
+                    ContentPart::Image(_) => Some("[image]".to_string()),
+                    ContentPart::Thinking { .. } => None,
+                })
+                .collect();
+            format!("{speaker}: {}\n", content.join("\n"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::MessageMetadata;
+    use crate::models::{Model, tests::config};
+
+    fn bundle(role: Role, text: &str) -> MessageBundle {
+        let config = config(Model::Ollama("llama3.2".to_string()));
+        MessageBundle::new(
+            Message::new(role, vec![ContentPart::Text(text.to_string())]),
+            MessageMetadata::new(&config),
+        )
+    }
+
+    // a system prompt then a turn per entry, the AI answering each (after a tool call where the entry says so)
+    fn chat(turns: &[(&str, bool)]) -> Vec<MessageBundle> {
+        let mut history = vec![bundle(Role::System, "be brief")];
+        for (text, tool) in turns {
+            history.push(bundle(Role::User, text));
+            if *tool {
+                history.push(bundle(Role::Ai, "calling"));
+                history.push(bundle(Role::Tool, "result"));
+            }
+            history.push(bundle(Role::Ai, &format!("re: {text}")));
+        }
+        history
+    }
+
+    fn texts(bundles: &[MessageBundle]) -> Vec<String> {
+        bundles.iter().map(|b| b.message.text()).collect()
+    }
+
+    #[test]
+    fn turns_split_at_user_messages() {
+        let history = chat(&[("one", false), ("two", true), ("three", false)]);
+        let turns = Turns::new(&history, &bundle(Role::User, "four"));
+        assert_eq!(turns.lead, 0..1);
+        assert_eq!(turns.turns, [1..3, 3..7, 7..9]);
+        assert!(!turns.in_progress);
+        assert!(!turns.required(2));
+
+        let turns = Turns::new(&history, &bundle(Role::Tool, "late result"));
+        assert!(turns.in_progress);
+        assert!(turns.required(2));
+        assert_eq!(
+            texts(&turns.assemble(&[false, true, false])),
+            texts(&history[..1])
+                .into_iter()
+                .chain(texts(&history[3..7]))
+                .collect::<Vec<_>>()
+        );
+
+        let turns = Turns::new(&[], &bundle(Role::User, "hi"));
+        assert_eq!(turns.lead, 0..0);
+        assert!(turns.turns.is_empty());
+    }
+
+    #[tokio::test]
+    async fn keep_last_turns_keeps_system_pinned_and_in_progress_turns() {
+        let mut history = chat(&[
+            ("one", false),
+            ("two", false),
+            ("three", true),
+            ("four", false),
+        ]);
+        history[3].pin();
+
+        let selection = KeepLastTurns(1)
+            .select(&history, &bundle(Role::User, "five"))
+            .await
+            .unwrap();
+        assert!(selection.next.is_none());
+        assert_eq!(
+            texts(&selection.history),
+            ["be brief", "two", "re: two", "four", "re: four"]
+        );
+
+        let selection = KeepLastTurns(0)
+            .select(&history, &bundle(Role::Tool, "late result"))
+            .await
+            .unwrap();
+        assert_eq!(
+            texts(&selection.history),
+            ["be brief", "two", "re: two", "four", "re: four"]
+        );
+
+        let selection = KeepLastTurns(10)
+            .select(&history, &bundle(Role::User, "five"))
+            .await
+            .unwrap();
+        assert_eq!(selection.history.len(), history.len());
+    }
+
+    #[tokio::test]
+    async fn token_budget_sets_aside_next_and_the_response() {
+        let history = chat(&[
+            ("one", false),
+            ("two two two two two two two two", false),
+            ("three", false),
+        ]);
+        let next = bundle(Role::User, "four");
+        let fixed =
+            estimate_tokens(&history[..1]) + estimate_tokens(std::slice::from_ref(&next)) + 1024;
+        let last = estimate_tokens(&history[5..7]);
+
+        let selection = TokenBudget(fixed + last)
+            .select(&history, &next)
+            .await
+            .unwrap();
+        assert_eq!(
+            texts(&selection.history),
+            ["be brief", "three", "re: three"]
+        );
+        let selection = TokenBudget(fixed + last - 1)
+            .select(&history, &next)
+            .await
+            .unwrap();
+        assert_eq!(texts(&selection.history), ["be brief"]);
+
+        // the oldest turn would fit once the middle one doesn't, but what's sent stays contiguous
+        let first = estimate_tokens(&history[1..3]);
+        let selection = TokenBudget(fixed + last + first)
+            .select(&history, &next)
+            .await
+            .unwrap();
+        assert_eq!(
+            texts(&selection.history),
+            ["be brief", "three", "re: three"]
+        );
+
+        let everything =
+            estimate_tokens(&history) + estimate_tokens(std::slice::from_ref(&next)) + 1024;
+        let selection = TokenBudget(everything)
+            .select(&history, &next)
+            .await
+            .unwrap();
+        assert_eq!(selection.history.len(), history.len());
+    }
+}
@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::{MessageError, Usage},
+    models::{Model, ModelConfig},
+};
+
+/// Mod purpose:
+/// Turn text into vectors with the config's model. LlmClient::embed owns sending (retries, budget, usage totals),
+/// this mod only knows each provider's embeddings wire format.
+
+#[allow(clippy::empty_line_after_doc_comments)]
+/// One vector per input, in input order
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Embeddings {
+    pub vectors: Vec<Vec<f32>>,
+    /// None when the provider doesn't report it (gemini)
+    pub usage: Option<Usage>,
+}
+
+impl Embeddings {
+    // batches are sent in order, so appending keeps vectors in input order
+    pub(crate) fn append(&mut self, batch: Embeddings) {
+        self.vectors.extend(batch.vectors);
+        self.usage = match (self.usage, batch.usage) {
+            (Some(mut usage), Some(next)) => {
+                usage += next;
+                Some(usage)
+            }
+            (usage, next) => usage.or(next),
+        };
+    }
+}
+
+/// Most inputs the provider takes in one request, None for providers aipi doesn't embed with
+pub(crate) fn max_batch(model: &Model) -> Option<usize> {
+    match model {
+        Model::ChatGpt(_) | Model::OpenAiCompatible { .. } => Some(2048),
+        Model::Gemini(_) => Some(100),
+        _ => None,
+    }
+}
+
+pub(crate) fn encode_request(
+    config: &ModelConfig,
+    inputs: &[&str],
+    dimensions: Option<usize>,
+) -> String {
+    let model = config.model.to_model_string();
+    match config.model {
+        Model::Gemini(_) => {
+            let model = format!("models/{}", model.unwrap_or_default());
+            let requests = inputs
+                .iter()
+                .map(|text| GeminiEmbedRequest {
+                    model: &model,
+                    content: GeminiEmbedContent {
+                        parts: [GeminiEmbedPart { text }],
+                    },
+                    output_dimensionality: dimensions,
+                })
+                .collect();
+            serde_json::to_string(&GeminiBatchEmbedRequest { requests })
+        }
+        _ => serde_json::to_string(&ChatGptEmbedRequest {
+            model,
+            input: inputs,
+            dimensions,
+            encoding_format: "float",
+        }),
+    }
+    .expect("correct serialization impl'd")
+}
+
+/// `inputs` is how many were sent, anything but one vector for each is an error rather than a misaligned result
+pub(crate) fn decode_response(
+    model: &Model,
+    content: &str,
+    inputs: usize,
+) -> Result<Embeddings, MessageError> {
+    let count = |found: usize| match found == inputs {
+        true => Ok(()),
+        false => Err(MessageError::Parse(format!(
+            "expected {inputs} embeddings, got {found}"
+        ))),
+    };
+    match model {
+        Model::Gemini(_) => {
+            let response = serde_json::from_str::<GeminiBatchEmbedResponse>(content)
+                .map_err(|e| MessageError::Parse(e.to_string()))?;
+            count(response.embeddings.len())?;
+            Ok(Embeddings {
+                vectors: response.embeddings.into_iter().map(|e| e.values).collect(),
+                usage: None,
+            })
+        }
+        _ => {
+            let mut response = serde_json::from_str::<ChatGptEmbedResponse>(content)
+                .map_err(|e| MessageError::Parse(e.to_string()))?;
+            count(response.data.len())?;
+            // openai numbers each vector by its input, don't rely on the list order
+            response.data.sort_by_key(|d| d.index);
+            // sorted, every input has its vector only if the indexes run 0..inputs
+            if let Some((position, d)) = response
+                .data
+                .iter()
+                .enumerate()
+                .find(|(position, d)| d.index != *position)
+            {
+                return Err(MessageError::Parse(format!(
+                    "embedding index {} is duplicated or out of range, expected {position}",
+                    d.index
+                )));
+            }
+            Ok(Embeddings {
+                vectors: response.data.into_iter().map(|d| d.embedding).collect(),
+                usage: response.usage.map(|u| Usage {
+                    input_tokens: u.prompt_tokens,
+                    ..Usage::default()
+                }),
+            })
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ChatGptEmbedRequest<'a> {
+    model: Option<&'a str>,
+    input: &'a [&'a str],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dimensions: Option<usize>,
+    encoding_format: &'static str,
+}
+
+#[derive(Deserialize)]
+struct ChatGptEmbedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct ChatGptEmbedUsage {
+    prompt_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct ChatGptEmbedResponse {
+    data: Vec<ChatGptEmbedding>,
+    usage: Option<ChatGptEmbedUsage>,
+}
+
+#[derive(Serialize)]
+struct GeminiEmbedPart<'a> {
+    text: &'a str,
+}
+
+#[derive(Serialize)]
+struct GeminiEmbedContent<'a> {
+    parts: [GeminiEmbedPart<'a>; 1],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiEmbedRequest<'a> {
+    model: &'a str,
+    content: GeminiEmbedContent<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output_dimensionality: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct GeminiBatchEmbedRequest<'a> {
+    requests: Vec<GeminiEmbedRequest<'a>>,
+}
+
+#[derive(Deserialize)]
+struct GeminiEmbedding {
+    values: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct GeminiBatchEmbedResponse {
+    embeddings: Vec<GeminiEmbedding>,
+}
@@ -21,12 +21,30 @@ static RELOAD_NEEDED: AtomicBool = AtomicBool::new(false);
 pub const API_KEY_ANTHROPIC: &str = "API_KEY_ANTHROPIC";
 pub const API_KEY_OPENAI: &str = "API_KEY_OPENAI";
 pub const API_KEY_GOOGLE: &str = "API_KEY_GOOGLE";
+pub const API_KEY_AZURE_OPENAI: &str = "API_KEY_AZURE_OPENAI";
+// optional, self-hosted servers frequently run without auth
+pub const API_KEY_OPENAI_COMPATIBLE: &str = "API_KEY_OPENAI_COMPATIBLE";
+
+// optional, point a provider at a proxy or stand-in server, e.g. "http://localhost:8080/v1"
+pub const BASE_URL_ANTHROPIC: &str = "BASE_URL_ANTHROPIC";
+pub const BASE_URL_OPENAI: &str = "BASE_URL_OPENAI";
+pub const BASE_URL_GOOGLE: &str = "BASE_URL_GOOGLE";
+pub const BASE_URL_OLLAMA: &str = "BASE_URL_OLLAMA";
+// required for azure, the resource endpoint, e.g. "https://my-resource.openai.azure.com"
+pub const BASE_URL_AZURE_OPENAI: &str = "BASE_URL_AZURE_OPENAI";
 
 #[derive(Clone, Debug)]
 struct AiPiEnvironment {
     anthropic_key: Option<SecretString>,
     openai_key: Option<SecretString>,
     google_key: Option<SecretString>,
+    azure_openai_key: Option<SecretString>,
+    openai_compatible_key: Option<SecretString>,
+    anthropic_base_url: Option<String>,
+    openai_base_url: Option<String>,
+    google_base_url: Option<String>,
+    ollama_base_url: Option<String>,
+    azure_openai_base_url: Option<String>,
 }
 
 impl Display for AiPiEnvironment {
@@ -44,7 +62,14 @@ impl AiPiEnvironment {
         AiPiEnvironment {
             anthropic_key: map_secret(env::var(API_KEY_ANTHROPIC)),
             openai_key: map_secret(env::var(API_KEY_OPENAI)),
-            google_key: map_secret(env::var(API_KEY_OPENAI)),
+            google_key: map_secret(env::var(API_KEY_GOOGLE)),
+            azure_openai_key: map_secret(env::var(API_KEY_AZURE_OPENAI)),
+            openai_compatible_key: map_secret(env::var(API_KEY_OPENAI_COMPATIBLE)),
+            anthropic_base_url: env::var(BASE_URL_ANTHROPIC).ok(),
+            openai_base_url: env::var(BASE_URL_OPENAI).ok(),
+            google_base_url: env::var(BASE_URL_GOOGLE).ok(),
+            ollama_base_url: env::var(BASE_URL_OLLAMA).ok(),
+            azure_openai_base_url: env::var(BASE_URL_AZURE_OPENAI).ok(),
         }
     }
 }
@@ -65,6 +90,18 @@ pub fn get_api_key(model: &Model) -> Result<SecretString, String> {
         Model::Claude(_) => (&rguard.anthropic_key, API_KEY_ANTHROPIC),
         Model::ChatGpt(_) => (&rguard.openai_key, API_KEY_OPENAI),
         Model::Gemini(_) => (&rguard.google_key, API_KEY_GOOGLE),
+        Model::AzureOpenAi { .. } => (&rguard.azure_openai_key, API_KEY_AZURE_OPENAI),
+        // an empty token means no auth header is sent
+        Model::OpenAiCompatible { .. } => {
+            return Ok(rguard
+                .openai_compatible_key
+                .clone()
+                .unwrap_or_else(|| SecretString::from("")));
+        }
+        // ollama has no auth of its own
+        Model::Ollama(_) => return Ok(SecretString::from("")),
+        // auth is up to the registered provider
+        Model::Custom { .. } => return Ok(SecretString::from("")),
         #[cfg(feature = "dev-tools")]
         _ => panic!("dev tools only"),
     };
@@ -72,6 +109,24 @@ pub fn get_api_key(model: &Model) -> Result<SecretString, String> {
     stateful_retrieve_key(key).map_err(|_| format!("Must set {env_var} in your env"))
 }
 
+/// Base url override for the model's provider, if one is set in the env
+pub fn get_base_url(model: &Model) -> Option<String> {
+    let rguard = ENVIRONMENT.read().expect("Guard poisoned");
+
+    match model {
+        Model::Claude(_) => rguard.anthropic_base_url.clone(),
+        Model::ChatGpt(_) => rguard.openai_base_url.clone(),
+        Model::Gemini(_) => rguard.google_base_url.clone(),
+        // the server's url is part of the model itself
+        Model::OpenAiCompatible { .. } => None,
+        Model::AzureOpenAi { .. } => rguard.azure_openai_base_url.clone(),
+        Model::Ollama(_) => rguard.ollama_base_url.clone(),
+        Model::Custom { .. } => None,
+        #[cfg(feature = "dev-tools")]
+        _ => panic!("dev tools only"),
+    }
+}
+
 fn stateful_retrieve_key(key: &Option<SecretString>) -> Result<SecretString, ()> {
     match key {
         None => {
@@ -0,0 +1,184 @@
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{message::MessageBundle, models::ModelConfig};
+
+/// Mod purpose:
+/// On-disk representation of a client's conversation so a chat can be resumed after a process restart.
+/// Files carry a format version; the api token is never written & is re-resolved from the environment on load.
+/// `.jsonl` paths are written as a header line (version & config) followed by one message bundle per line,
+/// which keeps appends & line-oriented tooling cheap. Any other path is written as a single json document.
+
+#[allow(clippy::empty_line_after_doc_comments)]
+pub const HISTORY_VERSION: u32 = 1;
+
+#[derive(Debug, Clone)]
+pub enum HistoryError {
+    Io(String),
+    Serde(String),
+    UnsupportedVersion(u32),
+    NoTokenSet(String),
+}
+
+impl Display for HistoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{self:?}"))
+    }
+}
+
+impl Error for HistoryError {}
+
+#[derive(Serialize, Deserialize)]
+struct HistoryDocument<'a> {
+    version: u32,
+    config: Cow<'a, ModelConfig>,
+    #[serde(default, skip_serializing_if = "<[MessageBundle]>::is_empty")]
+    messages: Cow<'a, [MessageBundle]>,
+}
+
+fn is_jsonl(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "jsonl")
+}
+
+fn io_err(e: std::io::Error) -> HistoryError {
+    HistoryError::Io(e.to_string())
+}
+
+fn serde_err(e: serde_json::Error) -> HistoryError {
+    HistoryError::Serde(e.to_string())
+}
+
+pub(crate) fn write_history(
+    path: &Path,
+    config: &ModelConfig,
+    messages: &[MessageBundle],
+) -> Result<(), HistoryError> {
+    let mut writer = BufWriter::new(File::create(path).map_err(io_err)?);
+
+    if is_jsonl(path) {
+        let header = HistoryDocument {
+            version: HISTORY_VERSION,
+            config: Cow::Borrowed(config),
+            messages: Cow::Borrowed(&[]),
+        };
+        serde_json::to_writer(&mut writer, &header).map_err(serde_err)?;
+        writeln!(writer).map_err(io_err)?;
+        for bundle in messages {
+            serde_json::to_writer(&mut writer, bundle).map_err(serde_err)?;
+            writeln!(writer).map_err(io_err)?;
+        }
+    } else {
+        let document = HistoryDocument {
+            version: HISTORY_VERSION,
+            config: Cow::Borrowed(config),
+            messages: Cow::Borrowed(messages),
+        };
+        serde_json::to_writer_pretty(&mut writer, &document).map_err(serde_err)?;
+    }
+
+    writer.flush().map_err(io_err)
+}
+
+pub(crate) fn read_history(path: &Path) -> Result<(ModelConfig, Vec<MessageBundle>), HistoryError> {
+    let reader = BufReader::new(File::open(path).map_err(io_err)?);
+
+    let document = if is_jsonl(path) {
+        let mut lines = reader.lines();
+        let header = lines
+            .next()
+            .ok_or_else(|| HistoryError::Serde("empty history file".to_string()))?
+            .map_err(io_err)?;
+        let mut document: HistoryDocument = serde_json::from_str(&header).map_err(serde_err)?;
+        let messages = lines
+            .filter(|line| !matches!(line, Ok(l) if l.trim().is_empty()))
+            .map(|line| serde_json::from_str(&line.map_err(io_err)?).map_err(serde_err))
+            .collect::<Result<Vec<MessageBundle>, HistoryError>>()?;
+        document.messages = Cow::Owned(messages);
+        document
+    } else {
+        serde_json::from_reader(reader).map_err(serde_err)?
+    };
+
+    // migrations from older versions slot in here as the format evolves
+    match document.version {
+        HISTORY_VERSION => Ok((document.config.into_owned(), document.messages.into_owned())),
+        v => Err(HistoryError::UnsupportedVersion(v)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::LlmClient,
+        message::{Message, MessageMetadata, Usage},
+        models::{Model, tests::config},
+    };
+    use secrecy::{ExposeSecret, SecretString};
+    use serde_json::Value;
+
+    #[test]
+    fn saved_history_loads_back_without_its_token() {
+        let dir = std::env::temp_dir().join(format!("aipi-history-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut config = config(Model::Ollama("llama3.2".to_string()));
+        config.system_prompt = Some("Be brief.".to_string());
+        config.token = SecretString::from("sk-secret");
+        let mut client = LlmClient::new(config);
+        let answer = MessageMetadata::new(&client.config).with_usage(Some(Usage {
+            input_tokens: 4,
+            output_tokens: 2,
+            ..Usage::default()
+        }));
+        client.message_history.extend([
+            MessageBundle::new(
+                Message::from_user("Hi".to_string()),
+                MessageMetadata::new(&client.config),
+            ),
+            MessageBundle::new(Message::from_ai("Hello".to_string()), answer),
+        ]);
+
+        for name in ["history.json", "history.jsonl"] {
+            let path = dir.join(name);
+            client.save_history(&path).unwrap();
+            assert!(
+                !std::fs::read_to_string(&path)
+                    .unwrap()
+                    .contains("sk-secret")
+            );
+
+            let loaded = LlmClient::from_history(&path).unwrap();
+            assert_eq!(loaded.config.token.expose_secret(), "");
+            assert_eq!(loaded.config.system_prompt, client.config.system_prompt);
+            let messages = |c: &LlmClient| {
+                c.message_history
+                    .iter()
+                    .map(|b| b.message.clone())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(messages(&loaded), messages(&client));
+            // running totals pick up where the saved conversation left off
+            assert_eq!(loaded.total_usage.total_tokens(), 6);
+        }
+
+        let path = dir.join("history.json");
+        let mut saved: Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        saved["version"] = Value::from(HISTORY_VERSION + 1);
+        std::fs::write(&path, saved.to_string()).unwrap();
+        assert!(matches!(
+            read_history(&path),
+            Err(HistoryError::UnsupportedVersion(v)) if v == HISTORY_VERSION + 1
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
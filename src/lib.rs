@@ -1,4 +1,12 @@
+pub mod chunking;
 pub mod client;
+pub mod context;
+pub mod embedding;
 pub mod environment;
+pub mod history;
 pub mod message;
 pub mod models;
+pub mod pricing;
+pub mod provider;
+pub mod retrieval;
+pub mod tool;
@@ -1,10 +1,17 @@
+pub mod image;
 pub mod serde;
 
-use std::{error::Error, fmt::Display};
+use std::{error::Error, fmt::Display, iter::Sum, ops::AddAssign};
 
+use ::serde::{Deserialize, Serialize};
 use chrono::Utc;
 
-use crate::models::{ModelConfig, Role};
+use crate::{
+    message::image::Image,
+    models::{ModelConfig, Role},
+    pricing::get_pricing,
+    tool::{ToolCall, ToolResult},
+};
 
 /// Mod purpose:
 /// Define our local notion of a "message" as representing an exchange between two parties (AI/Human, AI/AI, AI/Something Else?)
@@ -21,37 +28,136 @@ use crate::models::{ModelConfig, Role};
 /// 2025-08-15: decided to go with "MessageBundle" as the primary unit of transfer within the crate, with client exposing a clean interface to Message.
 /// I'm starting to feel like I'm reinventing two wheels simultaneously, but I think it makes sense to have the client hold some persistent notion of config
 /// so we can "swap" at the client level, with config at the message level used for historical reference only.
+///
+/// 2026-10-18: Message content is an ordered list of typed ContentParts rather than a single String.
+/// Providers answer in blocks (text, tool use, thinking) and flattening them lost data; each serde impl maps parts onto its native block format.
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Message {
     pub role: Role,
-    pub content: String,
+    pub content: Vec<ContentPart>,
+}
+
+/// A single typed piece of message content; parts are kept in the order the provider produced them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ContentPart {
+    Text(String),
+    Image(Image),
+    /// a tool the AI asked to have run
+    ToolUse(ToolCall),
+    /// the outcome of a tool call, sent back to the AI
+    ToolResult(ToolResult),
+    /// model reasoning; claude issues a signature that must accompany thinking sent back to it
+    Thinking {
+        text: String,
+        signature: Option<String>,
+    },
 }
 
 impl Message {
+    pub fn new(role: Role, content: Vec<ContentPart>) -> Message {
+        Message { role, content }
+    }
+
     pub fn from_user(content: String) -> Message {
-        Message {
-            role: Role::User,
-            content,
-        }
+        Message::new(Role::User, vec![ContentPart::Text(content)])
     }
 
     pub fn from_ai(content: String) -> Message {
-        Message {
-            role: Role::Ai,
-            content,
-        }
+        Message::new(Role::Ai, vec![ContentPart::Text(content)])
     }
 
     pub fn from_system(content: String) -> Message {
-        Message {
-            role: Role::System,
-            content,
+        Message::new(Role::System, vec![ContentPart::Text(content)])
+    }
+
+    pub fn from_tool_results(tool_results: Vec<ToolResult>) -> Message {
+        Message::new(
+            Role::Tool,
+            tool_results
+                .into_iter()
+                .map(ContentPart::ToolResult)
+                .collect(),
+        )
+    }
+
+    pub fn with_image(mut self, image: Image) -> Self {
+        self.content.push(ContentPart::Image(image));
+        self
+    }
+
+    pub fn has_images(&self) -> bool {
+        self.content
+            .iter()
+            .any(|p| matches!(p, ContentPart::Image(_)))
+    }
+
+    /// All text parts joined together, ignoring every other kind of part
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|p| match p {
+                ContentPart::Text(t) => Some(t.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn tool_calls(&self) -> Vec<&ToolCall> {
+        self.content
+            .iter()
+            .filter_map(|p| match p {
+                ContentPart::ToolUse(c) => Some(c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn tool_results(&self) -> Vec<&ToolResult> {
+        self.content
+            .iter()
+            .filter_map(|p| match p {
+                ContentPart::ToolResult(r) => Some(r),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // lets serializers keep the simple string form on the wire when that's all there is
+    pub(crate) fn as_plain_text(&self) -> Option<&str> {
+        match self.content.as_slice() {
+            [ContentPart::Text(t)] => Some(t),
+            _ => None,
         }
     }
 }
 
+/// Why the AI stopped generating, normalized across providers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum StopReason {
+    /// finished its turn naturally
+    EndTurn,
+    /// cut off by the config's max_tokens
+    MaxTokens,
+    /// hit one of the config's stop sequences (openai & ollama report these as EndTurn)
+    StopSequence,
+    /// stopped to have tools run
+    ToolUse,
+    /// withheld or cut short by the provider's safety filtering
+    ContentFilter,
+    /// anything else, as the provider reported it
+    Other(String),
+}
+
+/// A typed increment of a streamed AI response
 #[derive(Debug, Clone, PartialEq)]
+pub enum StreamDelta {
+    Text(String),
+    /// a complete tool call; calls are assembled from their fragments & delivered once the response is complete
+    ToolCall(ToolCall),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MessageTimestamp(chrono::DateTime<Utc>);
 
 impl MessageTimestamp {
@@ -60,10 +166,19 @@ impl MessageTimestamp {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageMetadata {
     timestamp: MessageTimestamp,
     config: ModelConfig,
+    /// only present on AI responses
+    #[serde(default)]
+    usage: Option<Usage>,
+    /// only present on AI responses
+    #[serde(default)]
+    stop_reason: Option<StopReason>,
+    /// always sent, whatever the client's context strategy
+    #[serde(default)]
+    pinned: bool,
 }
 
 // TODO-5: Metadata integrates with the notion of chat history simply, but not efficiently
@@ -78,11 +193,90 @@ impl MessageMetadata {
         MessageMetadata {
             timestamp: MessageTimestamp::now(),
             config: config.clone(),
+            usage: None,
+            stop_reason: None,
+            pinned: false,
         }
     }
+
+    pub fn with_usage(mut self, usage: Option<Usage>) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    pub fn with_stop_reason(mut self, stop_reason: Option<StopReason>) -> Self {
+        self.stop_reason = stop_reason;
+        self
+    }
+
+    pub fn timestamp(&self) -> &MessageTimestamp {
+        &self.timestamp
+    }
+
+    pub fn config(&self) -> &ModelConfig {
+        &self.config
+    }
+
+    pub fn usage(&self) -> Option<&Usage> {
+        self.usage.as_ref()
+    }
+
+    pub fn stop_reason(&self) -> Option<&StopReason> {
+        self.stop_reason.as_ref()
+    }
+
+    pub fn pinned(&self) -> bool {
+        self.pinned
+    }
 }
 
-#[derive(Debug, Clone)]
+/// Token counts as billed by the provider, normalized across providers:
+/// input_tokens excludes prompt tokens read from or written to the cache, output_tokens includes reasoning
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_read_tokens: u64,
+    pub cache_write_tokens: u64,
+    /// breakdown of output_tokens spent on reasoning, where the provider reports it
+    pub reasoning_tokens: u64,
+}
+
+impl Usage {
+    pub fn total_tokens(&self) -> u64 {
+        self.input_tokens + self.output_tokens + self.cache_read_tokens + self.cache_write_tokens
+    }
+
+    // streamed usage arrives as running totals, possibly a field at a time
+    pub(crate) fn merge_latest(&mut self, other: Usage) {
+        self.input_tokens = self.input_tokens.max(other.input_tokens);
+        self.output_tokens = self.output_tokens.max(other.output_tokens);
+        self.cache_read_tokens = self.cache_read_tokens.max(other.cache_read_tokens);
+        self.cache_write_tokens = self.cache_write_tokens.max(other.cache_write_tokens);
+        self.reasoning_tokens = self.reasoning_tokens.max(other.reasoning_tokens);
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, rhs: Self) {
+        self.input_tokens += rhs.input_tokens;
+        self.output_tokens += rhs.output_tokens;
+        self.cache_read_tokens += rhs.cache_read_tokens;
+        self.cache_write_tokens += rhs.cache_write_tokens;
+        self.reasoning_tokens += rhs.reasoning_tokens;
+    }
+}
+
+impl Sum for Usage {
+    fn sum<I: Iterator<Item = Usage>>(iter: I) -> Self {
+        iter.fold(Usage::default(), |mut acc, u| {
+            acc += u;
+            acc
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageBundle {
     pub metadata: MessageMetadata,
     pub message: Message,
@@ -92,17 +286,138 @@ impl MessageBundle {
     pub fn new(message: Message, metadata: MessageMetadata) -> Self {
         MessageBundle { message, metadata }
     }
+
+    pub fn usage(&self) -> Option<&Usage> {
+        self.metadata.usage()
+    }
+
+    pub fn stop_reason(&self) -> Option<&StopReason> {
+        self.metadata.stop_reason()
+    }
+
+    /// Keep the message's turn in every request, see context::ContextStrategy
+    pub fn pin(&mut self) {
+        self.metadata.pinned = true;
+    }
+
+    pub fn unpin(&mut self) {
+        self.metadata.pinned = false;
+    }
+
+    // joins a continuation onto a response that was cut off, the continuation's stop reason wins
+    pub(crate) fn stitch(&mut self, continuation: MessageBundle) {
+        let mut parts = continuation.message.content.into_iter();
+        if let (Some(ContentPart::Text(text)), Some(ContentPart::Text(next))) =
+            (self.message.content.last_mut(), parts.as_slice().first())
+        {
+            text.push_str(next);
+            parts.next();
+        }
+        self.message.content.extend(parts);
+
+        self.metadata.usage = match (self.metadata.usage, continuation.metadata.usage) {
+            (Some(mut usage), Some(next)) => {
+                usage += next;
+                Some(usage)
+            }
+            (usage, next) => usage.or(next),
+        };
+        self.metadata.stop_reason = continuation.metadata.stop_reason;
+    }
+
+    /// USD cost of the response, None if it carries no usage or its model has no known pricing
+    pub fn cost(&self) -> Option<f64> {
+        let usage = self.usage()?;
+        get_pricing(&self.metadata.config.model).map(|p| p.cost(usage))
+    }
+}
+
+/// Alternative AI responses to the same message, see LlmClient::send_candidates
+#[derive(Debug, Clone)]
+pub struct Candidates {
+    /// the message every response answers
+    pub request: MessageBundle,
+    /// in the order the provider numbered them
+    pub responses: Vec<MessageBundle>,
+    /// across every response; responses carry their own usage only when each was a separate request
+    pub usage: Option<Usage>,
+}
+
+impl Candidates {
+    /// Index of the response `scorer` rates highest (the first on ties), None if there are none or all scored NaN
+    pub fn best_by(&self, mut scorer: impl FnMut(&MessageBundle) -> f64) -> Option<usize> {
+        self.responses
+            .iter()
+            .map(&mut scorer)
+            .enumerate()
+            .filter(|(_, score)| !score.is_nan())
+            .fold(
+                None,
+                |best: Option<(usize, f64)>, (index, score)| match best {
+                    Some((_, best_score)) if best_score >= score => best,
+                    _ => Some((index, score)),
+                },
+            )
+            .map(|(index, _)| index)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum MessageError {
     Parse(String),
+    Image(String),
+    /// the model has no built-in wire format, i.e. a custom model that belongs to its registered provider
+    Unsupported(String),
 }
 
 impl Display for MessageError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("self:?"))
+        f.write_fmt(format_args!("{self:?}"))
     }
 }
 
 impl Error for MessageError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Model, tests::config};
+
+    fn candidates(texts: &[&str]) -> Candidates {
+        let config = config(Model::Ollama("llama3.2".to_string()));
+        let bundle = |message| MessageBundle::new(message, MessageMetadata::new(&config));
+        Candidates {
+            request: bundle(Message::from_user("Hi".to_string())),
+            responses: texts
+                .iter()
+                .map(|text| bundle(Message::from_ai(text.to_string())))
+                .collect(),
+            usage: None,
+        }
+    }
+
+    #[test]
+    fn best_by_picks_the_highest_score_first_on_ties() {
+        let candidates = candidates(&["a", "ccc", "bb", "ddd"]);
+        assert_eq!(
+            candidates.best_by(|b| b.message.text().len() as f64),
+            Some(1)
+        );
+        assert_eq!(
+            candidates.best_by(|b| -(b.message.text().len() as f64)),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn best_by_skips_nan_scores() {
+        let candidates = candidates(&["a", "bb", "ccc"]);
+        let scored = |b: &MessageBundle| match b.message.text().as_str() {
+            "ccc" => f64::NAN,
+            text => text.len() as f64,
+        };
+        assert_eq!(candidates.best_by(scored), Some(1));
+        assert_eq!(candidates.best_by(|_| f64::NAN), None);
+        assert_eq!(self::candidates(&[]).best_by(|_| 1.0), None);
+    }
+}
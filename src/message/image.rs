@@ -0,0 +1,138 @@
+use std::path::Path;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+
+use super::MessageError;
+
+/// Mod purpose:
+/// Image content for messages, normalized so each provider's serde impl can map it onto its own format.
+/// Local images are always sent inline as base64, remote ones by url.
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ImageSource {
+    Base64 {
+        media_type: String,
+        data: String,
+    },
+    Url {
+        url: String,
+        media_type: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Image {
+    pub source: ImageSource,
+}
+
+impl Image {
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Image, MessageError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)
+            .map_err(|e| MessageError::Image(format!("reading {}: {e}", path.display())))?;
+        Image::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Image, MessageError> {
+        let media_type = sniff_media_type(bytes).ok_or_else(|| {
+            MessageError::Image("unrecognized image format, expected png, jpeg, gif or webp".into())
+        })?;
+        Ok(Image {
+            source: ImageSource::Base64 {
+                media_type: media_type.to_string(),
+                data: STANDARD.encode(bytes),
+            },
+        })
+    }
+
+    /// media type is guessed from the url's extension, as not every provider fetches & sniffs remote images itself
+    pub fn from_url(url: String) -> Image {
+        let media_type = media_type_from_extension(&url).map(str::to_string);
+        Image {
+            source: ImageSource::Url { url, media_type },
+        }
+    }
+
+    /// The image as a url, inlining base64 data as a data url
+    pub(crate) fn to_url(&self) -> String {
+        match &self.source {
+            ImageSource::Base64 { media_type, data } => format!("data:{media_type};base64,{data}"),
+            ImageSource::Url { url, .. } => url.clone(),
+        }
+    }
+}
+
+fn sniff_media_type(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some("image/gif"),
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'W',
+            b'E',
+            b'B',
+            b'P',
+            ..,
+        ] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+fn media_type_from_extension(url: &str) -> Option<&'static str> {
+    // ignore any query string or fragment trailing the path
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let extension = path.rsplit_once('.')?.1.to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+    #[test]
+    fn bytes_are_sniffed_and_base64_encoded() {
+        let image = Image::from_bytes(PNG).unwrap();
+        assert_eq!(
+            image.source,
+            ImageSource::Base64 {
+                media_type: "image/png".to_string(),
+                data: "iVBORw0KGgo=".to_string(),
+            }
+        );
+        assert_eq!(image.to_url(), "data:image/png;base64,iVBORw0KGgo=");
+        assert!(matches!(
+            Image::from_bytes(b"%PDF-1.7"),
+            Err(MessageError::Image(_))
+        ));
+    }
+
+    #[test]
+    fn url_media_type_comes_from_the_extension() {
+        let media_type = |url: &str| match Image::from_url(url.to_string()).source {
+            ImageSource::Url { media_type, .. } => media_type,
+            other => panic!("unexpected source {other:?}"),
+        };
+        assert_eq!(
+            media_type("https://example.com/chart.JPG?size=large"),
+            Some("image/jpeg".to_string())
+        );
+        assert_eq!(media_type("https://example.com/chart"), None);
+    }
+}
@@ -1,16 +1,19 @@
-use chatgpt::{ChatGptRequest, ChatGptResponse};
-use claude::{ClaudeRequest, ClaudeResponse};
+use chatgpt::{ChatGptErrorResponse, ChatGptOutMessage, ChatGptRequest, ChatGptResponse};
+use claude::{ClaudeErrorResponse, ClaudeOutMessage, ClaudeRequest, ClaudeResponse};
+use gemini::{GeminiContent, GeminiErrorResponse, GeminiRequest, GeminiResponse};
+use ollama::{OllamaErrorResponse, OllamaOutMessage, OllamaRequest, OllamaResponse};
 use serde::{Serialize, Serializer, ser::SerializeSeq};
+use serde_json::Value;
 
 mod chatgpt;
 mod claude;
+mod gemini;
+mod ollama;
+pub(crate) mod stream;
 
-use crate::{
-    client::LlmClient,
-    models::{Model, ModelConfig},
-};
+use crate::models::{Model, ModelConfig};
 
-use super::{Message, MessageBundle, MessageError};
+use super::{Message, MessageBundle, MessageError, StopReason, Usage};
 
 pub trait ToMessage {
     fn to_message(&self) -> Message;
@@ -27,16 +30,14 @@ impl<'a> Serialize for MessageList<'a> {
     where
         S: Serializer,
     {
-        let mut seq = serializer.serialize_seq(Some(self.prev.len() + 1))?;
+        // a single message may expand to several on the wire (e.g. openai tool results), so no length hint
+        let mut seq = serializer.serialize_seq(None)?;
 
-        for m in self.prev {
-            let role = &m.message.role.as_string(self.model);
-            let out = select_out_message(self.model, role, &m.message.content);
-            SerializeSeq::serialize_element(&mut seq, &out)?;
+        for m in self.prev.iter().chain(std::iter::once(self.next)) {
+            for out in select_out_messages(self.model, &m.message) {
+                SerializeSeq::serialize_element(&mut seq, &out)?;
+            }
         }
-        let role = &self.next.message.role.as_string(self.model);
-        let out = select_out_message(self.model, role, &self.next.message.content);
-        SerializeSeq::serialize_element(&mut seq, &out)?;
         seq.end()
     }
 }
@@ -44,37 +45,95 @@ impl<'a> Serialize for MessageList<'a> {
 #[derive(Serialize)]
 #[serde(untagged)]
 enum AnyOutMsg<'a> {
-    Claude { role: &'a str, content: &'a str },
-    ChatGpt { role: &'a str, content: &'a str },
+    Claude(ClaudeOutMessage<'a>),
+    ChatGpt(ChatGptOutMessage<'a>),
+    Gemini(GeminiContent<'a>),
+    Ollama(OllamaOutMessage<'a>),
 }
 
-fn select_out_message<'a>(model: &Model, role: &'a str, content: &'a str) -> AnyOutMsg<'a> {
+fn select_out_messages<'a>(model: &Model, message: &'a Message) -> Vec<AnyOutMsg<'a>> {
     match model {
-        Model::Claude(_) => AnyOutMsg::Claude { role, content },
-        Model::ChatGpt(_) => AnyOutMsg::ChatGpt { role, content },
-        _ => todo!("gem"),
+        Model::Claude(_) => vec![AnyOutMsg::Claude(ClaudeOutMessage::new(message, model))],
+        Model::ChatGpt(_) | Model::OpenAiCompatible { .. } | Model::AzureOpenAi { .. } => {
+            ChatGptOutMessage::from_message(message, model)
+                .into_iter()
+                .map(AnyOutMsg::ChatGpt)
+                .collect()
+        }
+        Model::Gemini(_) => vec![AnyOutMsg::Gemini(GeminiContent::new(message, model))],
+        Model::Ollama(_) => OllamaOutMessage::from_message(message, model)
+            .into_iter()
+            .map(AnyOutMsg::Ollama)
+            .collect(),
+        // ModelRequestWrapper::new refuses custom models, so there's never a request to put these in
+        Model::Custom { .. } => Vec::new(),
+        #[cfg(feature = "dev-tools")]
+        Model::None => panic!("dev tools only"),
     }
 }
 
 pub(crate) enum ModelRequestWrapper<'a> {
     Claude(ClaudeRequest<'a>),
     ChatGpt(ChatGptRequest<'a>),
-    Gemini,
+    Gemini(GeminiRequest<'a>),
+    Ollama(OllamaRequest<'a>),
 }
 
 impl<'a> ModelRequestWrapper<'a> {
-    pub(crate) fn new(next: &'a MessageBundle, client: &'a LlmClient) -> Self {
-        match client.config.model {
+    pub(crate) fn new(
+        config: &'a ModelConfig,
+        history: &'a [MessageBundle],
+        next: &'a MessageBundle,
+        stream: bool,
+    ) -> Result<Self, MessageError> {
+        // a deserialized config skips the builder's check
+        if config.model.is_versionless() {
+            return Err(MessageError::Unsupported(format!(
+                "{} needs a model version to send requests to",
+                config.model
+            )));
+        }
+        let wrapped = match config.model {
             Model::Claude(_) => {
-                let req = ClaudeRequest { next, client };
+                let req = ClaudeRequest {
+                    config,
+                    history,
+                    next,
+                    stream,
+                };
                 ModelRequestWrapper::Claude(req)
             }
-            Model::ChatGpt(_) => {
-                let req = ChatGptRequest { next, client };
+            Model::ChatGpt(_) | Model::OpenAiCompatible { .. } | Model::AzureOpenAi { .. } => {
+                let req = ChatGptRequest {
+                    config,
+                    history,
+                    next,
+                    stream,
+                };
                 ModelRequestWrapper::ChatGpt(req)
             }
-            _ => todo!("gem"),
-        }
+            Model::Gemini(_) => {
+                let req = GeminiRequest {
+                    config,
+                    history,
+                    next,
+                };
+                ModelRequestWrapper::Gemini(req)
+            }
+            Model::Ollama(_) => {
+                let req = OllamaRequest {
+                    config,
+                    history,
+                    next,
+                    stream,
+                };
+                ModelRequestWrapper::Ollama(req)
+            }
+            Model::Custom { .. } => return Err(custom_model(&config.model)),
+            #[cfg(feature = "dev-tools")]
+            Model::None => panic!("dev tools only"),
+        };
+        Ok(wrapped)
     }
 
     // TODO-4 determine if this being panicable is acceptable given we should be able to verify correctness of serialization
@@ -85,7 +144,8 @@ impl<'a> ModelRequestWrapper<'a> {
             Self::ChatGpt(req) => {
                 serde_json::to_string(&req).expect("correct serialization impl'd")
             }
-            Self::Gemini => todo!("gem"),
+            Self::Gemini(req) => serde_json::to_string(&req).expect("correct serialization impl'd"),
+            Self::Ollama(req) => serde_json::to_string(&req).expect("correct serialization impl'd"),
         }
     }
 }
@@ -94,7 +154,8 @@ impl<'a> ModelRequestWrapper<'a> {
 pub(crate) enum ModelResponseWrapper {
     Claude(ClaudeResponse),
     ChatGpt(ChatGptResponse),
-    Gemini,
+    Gemini(GeminiResponse),
+    Ollama(OllamaResponse),
 }
 
 impl From<ModelResponseWrapper> for Message {
@@ -102,23 +163,98 @@ impl From<ModelResponseWrapper> for Message {
         match wrapper {
             ModelResponseWrapper::Claude(r) => Message::from_claude_response(r),
             ModelResponseWrapper::ChatGpt(r) => Message::from_chatgpt_response(r),
-            ModelResponseWrapper::Gemini => todo!("gem"),
+            ModelResponseWrapper::Gemini(r) => Message::from_gemini_response(r),
+            ModelResponseWrapper::Ollama(r) => Message::from_ollama_response(r),
         }
     }
 }
 
 impl ModelResponseWrapper {
+    pub fn usage(&self) -> Option<Usage> {
+        match self {
+            ModelResponseWrapper::Claude(r) => r.usage.clone().map(Usage::from),
+            ModelResponseWrapper::ChatGpt(r) => r.usage.clone().map(Usage::from),
+            ModelResponseWrapper::Gemini(r) => r.usage_metadata.clone().map(Usage::from),
+            ModelResponseWrapper::Ollama(r) => r.usage(),
+        }
+    }
+
+    /// Every candidate in the order the provider numbered them, for requests that asked for several
+    pub(crate) fn into_candidates(self) -> Vec<(Message, Option<StopReason>)> {
+        match self {
+            ModelResponseWrapper::ChatGpt(mut r) => {
+                r.choices.sort_by_key(|c| c.index);
+                r.choices
+                    .into_iter()
+                    .map(|c| {
+                        let stop_reason = c.stop_reason();
+                        (Message::from_chatgpt_choice(c), stop_reason)
+                    })
+                    .collect()
+            }
+            ModelResponseWrapper::Gemini(mut r) => {
+                r.candidates.sort_by_key(|c| c.index);
+                r.candidates
+                    .into_iter()
+                    .map(|c| {
+                        let stop_reason = c.stop_reason();
+                        (Message::from_gemini_candidate(c), stop_reason)
+                    })
+                    .collect()
+            }
+            // only ever one
+            other => {
+                let stop_reason = other.stop_reason();
+                vec![(Message::from(other), stop_reason)]
+            }
+        }
+    }
+
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        match self {
+            ModelResponseWrapper::Claude(r) => {
+                r.stop_reason.as_deref().map(StopReason::from_claude)
+            }
+            ModelResponseWrapper::ChatGpt(r) => r.stop_reason(),
+            ModelResponseWrapper::Gemini(r) => r.stop_reason(),
+            ModelResponseWrapper::Ollama(r) => r.stop_reason(),
+        }
+    }
+
     pub fn parse_new(content: String, config: &'_ ModelConfig) -> Result<Self, MessageError> {
         let wrapped = match config.model {
             Model::Claude(_) => ModelResponseWrapper::Claude(
                 serde_json::from_str::<ClaudeResponse>(content.as_str())
                     .map_err(|e| MessageError::Parse(e.to_string()))?,
             ),
-            Model::ChatGpt(_) => ModelResponseWrapper::ChatGpt(
-                serde_json::from_str::<ChatGptResponse>(content.as_str())
+            Model::ChatGpt(_) | Model::OpenAiCompatible { .. } | Model::AzureOpenAi { .. } => {
+                let response = serde_json::from_str::<ChatGptResponse>(content.as_str())
+                    .map_err(|e| MessageError::Parse(e.to_string()))?;
+                if response.choices.is_empty() {
+                    return Err(MessageError::Parse("response has no choices".to_string()));
+                }
+                ModelResponseWrapper::ChatGpt(response)
+            }
+            Model::Gemini(_) => {
+                let response = serde_json::from_str::<GeminiResponse>(content.as_str())
+                    .map_err(|e| MessageError::Parse(e.to_string()))?;
+                if response.candidates.is_empty() {
+                    let reason = response
+                        .prompt_feedback
+                        .and_then(|f| f.block_reason)
+                        .map(|r| format!(", prompt blocked: {r}"))
+                        .unwrap_or_default();
+                    return Err(MessageError::Parse(format!(
+                        "response has no candidates{reason}"
+                    )));
+                }
+                ModelResponseWrapper::Gemini(response)
+            }
+            Model::Ollama(_) => ModelResponseWrapper::Ollama(
+                serde_json::from_str::<OllamaResponse>(content.as_str())
                     .map_err(|e| MessageError::Parse(e.to_string()))?,
             ),
-            Model::Gemini(_) => todo!("gem"),
+            Model::Custom { .. } => return Err(custom_model(&config.model)),
             #[cfg(feature = "dev-tools")]
             _ => panic!("dev tools only"),
         };
@@ -126,3 +262,238 @@ impl ModelResponseWrapper {
         Ok(wrapped)
     }
 }
+
+// custom models are coded by their registered provider, the client never hands them to these
+pub(crate) fn custom_model(model: &Model) -> MessageError {
+    MessageError::Unsupported(format!(
+        "{model} has no built-in wire format, it is sent by its registered provider"
+    ))
+}
+
+/// Provider-neutral view of an error body
+#[derive(Debug, Clone)]
+pub struct ErrorBody {
+    pub error_type: Option<String>,
+    pub code: Option<String>,
+    pub message: String,
+}
+
+/// None when the body isn't the provider's documented error shape (e.g. an html page from a proxy)
+pub(crate) fn parse_error_body(content: &str, model: &Model) -> Option<ErrorBody> {
+    match model {
+        Model::Claude(_) => serde_json::from_str::<ClaudeErrorResponse>(content)
+            .ok()
+            .map(|r| ErrorBody {
+                error_type: Some(r.error.r#type),
+                code: None,
+                message: r.error.message,
+            }),
+        Model::ChatGpt(_) | Model::OpenAiCompatible { .. } | Model::AzureOpenAi { .. } => {
+            serde_json::from_str::<ChatGptErrorResponse>(content)
+                .ok()
+                .map(|r| ErrorBody {
+                    error_type: r.error.r#type,
+                    code: r.error.code.filter(|c| !c.is_null()).map(|c| match c {
+                        Value::String(s) => s,
+                        other => other.to_string(),
+                    }),
+                    message: r.error.message,
+                })
+        }
+        Model::Gemini(_) => serde_json::from_str::<GeminiErrorResponse>(content)
+            .ok()
+            .map(|r| ErrorBody {
+                error_type: r.error.status,
+                code: r.error.code.map(|c| c.to_string()),
+                message: r.error.message,
+            }),
+        Model::Ollama(_) => serde_json::from_str::<OllamaErrorResponse>(content)
+            .ok()
+            .map(|r| ErrorBody {
+                error_type: None,
+                code: None,
+                message: r.error,
+            }),
+        Model::Custom { .. } => None,
+        #[cfg(feature = "dev-tools")]
+        Model::None => panic!("dev tools only"),
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::message::{ContentPart, Message, MessageMetadata, Usage};
+    use crate::models::{ChatGptVersion, ClaudeVersion, GeminiVersion, Role, tests::config};
+    use crate::tool::{ToolCall, ToolDefinition, ToolResult};
+    use serde_json::json;
+
+    // a config asking for a system prompt & offering one tool
+    pub(super) fn tool_config(model: Model) -> ModelConfig {
+        let mut config = config(model);
+        config.system_prompt = Some("Be brief.".to_string());
+        config.tools = vec![ToolDefinition::new(
+            "weather".to_string(),
+            "Weather for a city".to_string(),
+            json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+        )];
+        config
+    }
+
+    // a tool call & its result followed by a plain exchange, then `next`; openai style models lead with a system message
+    pub(super) fn encode(config: &ModelConfig, system_message: bool) -> Value {
+        let bundle = |message| MessageBundle::new(message, MessageMetadata::new(config));
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "weather".to_string(),
+            arguments: json!({"city": "Oslo"}),
+        };
+        let system = system_message.then(|| bundle(Message::from_system("Be brief.".to_string())));
+        let history: Vec<MessageBundle> = system
+            .into_iter()
+            .chain([
+                bundle(Message::from_user("Weather in Oslo?".to_string())),
+                bundle(Message::new(
+                    Role::Ai,
+                    vec![
+                        ContentPart::Text("Checking.".to_string()),
+                        ContentPart::ToolUse(call.clone()),
+                    ],
+                )),
+                bundle(Message::from_tool_results(vec![ToolResult::new(
+                    &call,
+                    "12C, rain".to_string(),
+                )])),
+                bundle(Message::from_ai("12C and raining.".to_string())),
+            ])
+            .collect();
+        let next = bundle(Message::from_user("Thanks".to_string()));
+        let payload = ModelRequestWrapper::new(config, &history, &next, false)
+            .unwrap()
+            .to_payload();
+        serde_json::from_str(&payload).unwrap()
+    }
+
+    pub(super) fn decode(
+        config: &ModelConfig,
+        body: &str,
+    ) -> (Message, Option<StopReason>, Option<Usage>) {
+        let wrapped = ModelResponseWrapper::parse_new(body.to_string(), config).unwrap();
+        let (stop_reason, usage) = (wrapped.stop_reason(), wrapped.usage());
+        (Message::from(wrapped), stop_reason, usage)
+    }
+
+    // every provider answers "Let me check." & asks for the weather in Bergen
+    pub(super) fn assert_tool_answer(message: &Message, call_id: &str) {
+        assert_eq!(message.role, Role::Ai);
+        assert_eq!(
+            message.content,
+            [
+                ContentPart::Text("Let me check.".to_string()),
+                ContentPart::ToolUse(ToolCall {
+                    id: call_id.to_string(),
+                    name: "weather".to_string(),
+                    arguments: json!({"city": "Bergen"}),
+                }),
+            ]
+        );
+    }
+
+    pub(super) fn usage(input_tokens: u64, output_tokens: u64) -> Option<Usage> {
+        Some(Usage {
+            input_tokens,
+            output_tokens,
+            ..Usage::default()
+        })
+    }
+
+    #[test]
+    fn error_bodies_decode_to_the_provider_neutral_shape() {
+        let decode = |model: Model, body: &str| {
+            parse_error_body(body, &model).map(|e| (e.error_type, e.code, e.message))
+        };
+        let some = |s: &str| Some(s.to_string());
+
+        assert_eq!(
+            decode(
+                Model::Claude(ClaudeVersion::Sonnet4),
+                r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+            ),
+            Some((some("overloaded_error"), None, "Overloaded".to_string()))
+        );
+        assert_eq!(
+            decode(
+                Model::ChatGpt(ChatGptVersion::Gpt5),
+                r#"{"error":{"message":"You exceeded your current quota.","type":"insufficient_quota","param":null,"code":"insufficient_quota"}}"#,
+            ),
+            Some((
+                some("insufficient_quota"),
+                some("insufficient_quota"),
+                "You exceeded your current quota.".to_string()
+            ))
+        );
+        // openai compatible servers are looser, numeric & null codes included
+        let compatible = Model::OpenAiCompatible {
+            model_id: "qwen3".to_string(),
+            base_url: "http://localhost:8000/v1".to_string(),
+        };
+        assert_eq!(
+            decode(
+                compatible.clone(),
+                r#"{"error":{"message":"Model not loaded","code":503}}"#
+            ),
+            Some((None, some("503"), "Model not loaded".to_string()))
+        );
+        assert_eq!(
+            decode(
+                compatible,
+                r#"{"error":{"message":"Bad input","code":null}}"#
+            ),
+            Some((None, None, "Bad input".to_string()))
+        );
+        assert_eq!(
+            decode(
+                Model::Gemini(GeminiVersion::Gemini25Flash),
+                r#"{"error":{"code":429,"message":"Resource has been exhausted.","status":"RESOURCE_EXHAUSTED"}}"#,
+            ),
+            Some((
+                some("RESOURCE_EXHAUSTED"),
+                some("429"),
+                "Resource has been exhausted.".to_string()
+            ))
+        );
+        // e.g. a proxy's error page
+        assert_eq!(
+            decode(
+                Model::Claude(ClaudeVersion::Sonnet4),
+                "<html><body>502 Bad Gateway</body></html>"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn custom_models_have_no_builtin_codec() {
+        let config = config(Model::Custom {
+            provider: "acme".to_string(),
+            model_id: "rocket-1".to_string(),
+        });
+        let next = MessageBundle::new(
+            crate::message::Message::from_user("Hi".to_string()),
+            crate::message::MessageMetadata::new(&config),
+        );
+        assert!(matches!(
+            ModelRequestWrapper::new(&config, &[], &next, false),
+            Err(MessageError::Unsupported(_))
+        ));
+        assert!(matches!(
+            ModelResponseWrapper::parse_new("{}".to_string(), &config),
+            Err(MessageError::Unsupported(_))
+        ));
+        assert!(matches!(
+            stream::decode_stream_line("data: {}", &config.model),
+            Err(MessageError::Unsupported(_))
+        ));
+        assert!(parse_error_body(r#"{"error":"nope"}"#, &config.model).is_none());
+    }
+}
@@ -1,16 +1,20 @@
 use crate::{
-    client::LlmClient,
-    message::{Message, MessageBundle, serde::MessageList},
+    message::{ContentPart, Message, MessageBundle, StopReason, Usage, serde::MessageList},
+    models::{Model, ModelConfig, Role},
+    tool::ToolCall,
 };
 use serde::{Deserialize, Serialize, Serializer, ser::SerializeStruct};
+use serde_json::{Value, json};
 
 /// Mod purpose:
 /// Specifically implement the guts of a chatgpt interaction according to openAI's API spec
 
 #[derive(Debug, Clone)]
 pub(crate) struct ChatGptRequest<'a> {
-    pub(crate) client: &'a LlmClient,
+    pub(crate) config: &'a ModelConfig,
+    pub(crate) history: &'a [MessageBundle],
     pub(crate) next: &'a MessageBundle,
+    pub(crate) stream: bool,
 }
 
 impl<'a> Serialize for ChatGptRequest<'a> {
@@ -18,18 +22,85 @@ impl<'a> Serialize for ChatGptRequest<'a> {
     where
         S: Serializer,
     {
-        let mut st = serializer.serialize_struct("ChatGptRequest", 4)?;
+        let config = self.config;
+        let mut st = serializer.serialize_struct(
+            "ChatGptRequest",
+            3 + usize::from(config.temperature.is_some())
+                + usize::from(config.top_p.is_some())
+                + usize::from(config.top_k.is_some())
+                + usize::from(!config.stop_sequences.is_empty())
+                + usize::from(config.seed.is_some())
+                + usize::from(config.presence_penalty.is_some())
+                + usize::from(config.frequency_penalty.is_some())
+                + usize::from(!config.logit_bias.is_empty())
+                + usize::from(config.candidates.is_some())
+                + 2 * usize::from(self.stream)
+                + usize::from(!config.tools.is_empty()),
+        )?;
 
-        st.serialize_field("model", &self.client.config.model.to_model_string())?;
-        st.serialize_field("max_completion_tokens", &self.client.config.max_tokens)?;
-        st.serialize_field("temperature", &self.client.config.temperature)?;
+        st.serialize_field("model", &self.config.model.to_model_string())?;
+        // compatible servers mostly only understand the field openai has since deprecated
+        let max_tokens_field = match config.model {
+            Model::OpenAiCompatible { .. } => "max_tokens",
+            _ => "max_completion_tokens",
+        };
+        st.serialize_field(max_tokens_field, &self.config.max_tokens)?;
+        // reasoning models reject anything but their default temperature, their configs never carry one
+        if let Some(temperature) = config.temperature {
+            st.serialize_field("temperature", &temperature)?;
+        }
+        if let Some(top_p) = config.top_p {
+            st.serialize_field("top_p", &top_p)?;
+        }
+        // not openai's, but vllm & friends take it (build rejects it for openai models)
+        if let Some(top_k) = config.top_k {
+            st.serialize_field("top_k", &top_k)?;
+        }
+        if !config.stop_sequences.is_empty() {
+            st.serialize_field("stop", &config.stop_sequences)?;
+        }
+        if let Some(seed) = config.seed {
+            st.serialize_field("seed", &seed)?;
+        }
+        if let Some(presence_penalty) = config.presence_penalty {
+            st.serialize_field("presence_penalty", &presence_penalty)?;
+        }
+        if let Some(frequency_penalty) = config.frequency_penalty {
+            st.serialize_field("frequency_penalty", &frequency_penalty)?;
+        }
+        // json object keys are strings, serde writes the token ids as such
+        if !config.logit_bias.is_empty() {
+            st.serialize_field("logit_bias", &config.logit_bias)?;
+        }
+        if let Some(n) = config.candidates {
+            st.serialize_field("n", &n)?;
+        }
+        if self.stream {
+            st.serialize_field("stream", &true)?;
+            st.serialize_field("stream_options", &json!({ "include_usage": true }))?;
+        }
+        if !config.tools.is_empty() {
+            let tools: Vec<ChatGptTool> = config
+                .tools
+                .iter()
+                .map(|t| ChatGptTool {
+                    r#type: "function",
+                    function: ChatGptFunction {
+                        name: &t.name,
+                        description: &t.description,
+                        parameters: &t.input_schema,
+                    },
+                })
+                .collect();
+            st.serialize_field("tools", &tools)?;
+        }
 
         st.serialize_field(
             "messages",
             &MessageList {
-                prev: &self.client.message_history,
+                prev: self.history,
                 next: self.next,
-                model: &self.client.config.model,
+                model: &self.config.model,
             },
         )?;
 
@@ -37,30 +108,478 @@ impl<'a> Serialize for ChatGptRequest<'a> {
     }
 }
 
+#[derive(Serialize)]
+struct ChatGptFunction<'a> {
+    name: &'a str,
+    description: &'a str,
+    parameters: &'a Value,
+}
+
+#[derive(Serialize)]
+struct ChatGptTool<'a> {
+    r#type: &'static str,
+    function: ChatGptFunction<'a>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ChatGptOutFunctionCall<'a> {
+    name: &'a str,
+    // openai sends & expects arguments as a json encoded string
+    arguments: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ChatGptOutToolCall<'a> {
+    id: &'a str,
+    r#type: &'static str,
+    function: ChatGptOutFunctionCall<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub(crate) enum ChatGptOutMessage<'a> {
+    Text {
+        role: String,
+        content: &'a str,
+    },
+    // text joined from several parts
+    OwnedText {
+        role: String,
+        content: String,
+    },
+    ToolCalls {
+        role: String,
+        content: Option<String>,
+        tool_calls: Vec<ChatGptOutToolCall<'a>>,
+    },
+    ToolResult {
+        role: String,
+        tool_call_id: &'a str,
+        content: &'a str,
+    },
+    // mixed text & images need the array form of content
+    Parts {
+        role: String,
+        content: Vec<ChatGptOutPart<'a>>,
+    },
+}
+
+#[derive(Serialize)]
+pub(crate) struct ChatGptImageUrl {
+    url: String,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ChatGptOutPart<'a> {
+    Text { text: &'a str },
+    ImageUrl { image_url: ChatGptImageUrl },
+}
+
+impl<'a> ChatGptOutMessage<'a> {
+    // openai takes one message per tool result, so a single message can fan out;
+    // thinking isn't accepted back by openai and is dropped
+    pub(crate) fn from_message(message: &'a Message, model: &Model) -> Vec<Self> {
+        // a "tool" message must answer a call by id, text sent alongside results goes as the user's
+        let role = match message.role {
+            Role::Tool => Role::User.as_string(model),
+            _ => message.role.as_string(model),
+        };
+        if let Some(text) = message.as_plain_text() {
+            return vec![ChatGptOutMessage::Text {
+                role,
+                content: text,
+            }];
+        }
+
+        let mut out: Vec<ChatGptOutMessage> = message
+            .tool_results()
+            .into_iter()
+            .map(|r| ChatGptOutMessage::ToolResult {
+                role: Role::Tool.as_string(model),
+                tool_call_id: &r.call_id,
+                content: &r.content,
+            })
+            .collect();
+
+        let text = message.text();
+        let tool_calls: Vec<ChatGptOutToolCall> = message
+            .tool_calls()
+            .into_iter()
+            .map(|c| ChatGptOutToolCall {
+                id: &c.id,
+                r#type: "function",
+                function: ChatGptOutFunctionCall {
+                    name: &c.name,
+                    arguments: c.arguments.to_string(),
+                },
+            })
+            .collect();
+
+        if !tool_calls.is_empty() {
+            out.push(ChatGptOutMessage::ToolCalls {
+                role,
+                content: Some(text).filter(|t| !t.is_empty()),
+                tool_calls,
+            });
+        } else if message.has_images() {
+            let content = message
+                .content
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text(text) => Some(ChatGptOutPart::Text { text }),
+                    ContentPart::Image(image) => Some(ChatGptOutPart::ImageUrl {
+                        image_url: ChatGptImageUrl {
+                            url: image.to_url(),
+                        },
+                    }),
+                    _ => None,
+                })
+                .collect();
+            out.push(ChatGptOutMessage::Parts { role, content });
+        } else if !text.is_empty() {
+            out.push(ChatGptOutMessage::OwnedText {
+                role,
+                content: text,
+            });
+        }
+        out
+    }
+}
+
 impl Message {
+    // parse_new turns away responses without choices, so there's always one here
     pub(crate) fn from_chatgpt_response(mut value: ChatGptResponse) -> Self {
-        let content = value
+        value
             .choices
             .pop()
-            .expect("gotta be something in here")
-            .message
-            .content;
-        Message::from_ai(content)
+            .map(Message::from_chatgpt_choice)
+            .unwrap_or_else(|| Message::new(Role::Ai, Vec::new()))
+    }
+
+    pub(crate) fn from_chatgpt_choice(choice: ChatGptContent) -> Self {
+        let message = choice.message;
+        let text = message
+            .content
+            .filter(|c| !c.is_empty())
+            .map(ContentPart::Text);
+        let tool_calls = message.tool_calls.unwrap_or_default().into_iter().map(|c| {
+            ContentPart::ToolUse(ToolCall {
+                id: c.id,
+                name: c.function.name,
+                // fall back to the raw string if the model produced invalid json
+                arguments: serde_json::from_str(&c.function.arguments)
+                    .unwrap_or(Value::String(c.function.arguments)),
+            })
+        });
+        Message::new(Role::Ai, text.into_iter().chain(tool_calls).collect())
     }
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ChatGptFunctionCall {
+    name: String,
+    arguments: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ChatGptToolCall {
+    id: String,
+    function: ChatGptFunctionCall,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub(crate) struct ChatGptMessageContent {
-    content: String,
+    content: Option<String>,
+    tool_calls: Option<Vec<ChatGptToolCall>>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub(crate) struct ChatGptContent {
-    pub(crate) index: usize, // currently unused, for multiplexing response
+    // which of the requested n choices this is
+    pub(crate) index: usize,
     pub(crate) message: ChatGptMessageContent,
+    pub(crate) finish_reason: Option<String>,
+}
+
+impl ChatGptContent {
+    pub(crate) fn stop_reason(&self) -> Option<StopReason> {
+        self.finish_reason.as_deref().map(StopReason::from_chatgpt)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ChatGptPromptTokenDetails {
+    pub(crate) cached_tokens: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ChatGptCompletionTokenDetails {
+    pub(crate) reasoning_tokens: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ChatGptUsage {
+    pub(crate) prompt_tokens: u64,
+    pub(crate) completion_tokens: u64,
+    pub(crate) prompt_tokens_details: Option<ChatGptPromptTokenDetails>,
+    pub(crate) completion_tokens_details: Option<ChatGptCompletionTokenDetails>,
+}
+
+impl From<ChatGptUsage> for Usage {
+    fn from(value: ChatGptUsage) -> Self {
+        // openai counts cached tokens within prompt_tokens
+        let cached = value
+            .prompt_tokens_details
+            .and_then(|d| d.cached_tokens)
+            .unwrap_or_default();
+        Usage {
+            input_tokens: value.prompt_tokens.saturating_sub(cached),
+            output_tokens: value.completion_tokens,
+            cache_read_tokens: cached,
+            cache_write_tokens: 0,
+            reasoning_tokens: value
+                .completion_tokens_details
+                .and_then(|d| d.reasoning_tokens)
+                .unwrap_or_default(),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub(crate) struct ChatGptResponse {
     pub(crate) choices: Vec<ChatGptContent>,
+    pub(crate) usage: Option<ChatGptUsage>,
+}
+
+impl ChatGptResponse {
+    // the same choice from_chatgpt_response takes
+    pub(crate) fn stop_reason(&self) -> Option<StopReason> {
+        self.choices.last()?.stop_reason()
+    }
+}
+
+impl StopReason {
+    // openai doesn't tell a stop sequence apart from a natural end, both are "stop"
+    pub(crate) fn from_chatgpt(reason: &str) -> Self {
+        match reason {
+            "stop" => StopReason::EndTurn,
+            "length" => StopReason::MaxTokens,
+            "tool_calls" | "function_call" => StopReason::ToolUse,
+            "content_filter" => StopReason::ContentFilter,
+            other => StopReason::Other(other.to_string()),
+        }
+    }
+}
+
+// {"error":{"message":"...","type":"invalid_request_error","param":null,"code":"context_length_exceeded"}}
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ChatGptErrorBody {
+    pub(crate) message: String,
+    pub(crate) r#type: Option<String>,
+    // usually a string, but not guaranteed to be
+    pub(crate) code: Option<Value>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ChatGptErrorResponse {
+    pub(crate) error: ChatGptErrorBody,
+}
+
+// streamed responses arrive as chat.completion.chunk frames, terminated by a literal [DONE]
+pub(crate) const CHATGPT_STREAM_DONE: &str = "[DONE]";
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ChatGptStreamDelta {
+    pub(crate) content: Option<String>,
+    // a call's id & name come on its first fragment, its arguments are spread across the rest
+    #[serde(default)]
+    pub(crate) tool_calls: Vec<ChatGptStreamToolCall>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ChatGptStreamToolCall {
+    pub(crate) index: usize,
+    pub(crate) id: Option<String>,
+    pub(crate) function: Option<ChatGptStreamFunction>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct ChatGptStreamFunction {
+    pub(crate) name: Option<String>,
+    pub(crate) arguments: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ChatGptStreamChoice {
+    pub(crate) delta: ChatGptStreamDelta,
+    // only on the choice's last chunk
+    pub(crate) finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ChatGptStreamChunk {
+    pub(crate) choices: Vec<ChatGptStreamChoice>,
+    // only on the final chunk, and only when requested via stream_options
+    pub(crate) usage: Option<ChatGptUsage>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ModelResponseWrapper;
+    use super::super::tests::{self, assert_tool_answer, decode, encode, tool_config};
+    use super::*;
+    use crate::message::{
+        MessageError, StopReason,
+        image::{Image, ImageSource},
+    };
+    use crate::models::{ChatGptVersion, tests::config};
+    use crate::tool::ToolResult;
+
+    #[test]
+    fn chatgpt_codec_round_trip() {
+        let config = tool_config(Model::ChatGpt(ChatGptVersion::Gpt5));
+        assert_eq!(
+            encode(&config, true),
+            json!({
+                "model": "gpt-5",
+                "max_completion_tokens": 1024,
+                "tools": [{
+                    "type": "function",
+                    "function": {
+                        "name": "weather",
+                        "description": "Weather for a city",
+                        "parameters": {"type": "object", "properties": {"city": {"type": "string"}}},
+                    },
+                }],
+                "messages": [
+                    {"role": "developer", "content": "Be brief."},
+                    {"role": "user", "content": "Weather in Oslo?"},
+                    {"role": "assistant", "content": "Checking.", "tool_calls": [
+                        {"id": "call_1", "type": "function", "function": {"name": "weather", "arguments": "{\"city\":\"Oslo\"}"}},
+                    ]},
+                    {"role": "tool", "tool_call_id": "call_1", "content": "12C, rain"},
+                    {"role": "assistant", "content": "12C and raining."},
+                    {"role": "user", "content": "Thanks"},
+                ],
+            })
+        );
+
+        let (message, stop_reason, usage) = decode(
+            &config,
+            r#"{"id":"chatcmpl-1","object":"chat.completion","model":"gpt-5",
+                "choices":[{"index":0,"finish_reason":"tool_calls","message":{"role":"assistant","content":"Let me check.",
+                "tool_calls":[{"id":"call_2","type":"function","function":{"name":"weather","arguments":"{\"city\":\"Bergen\"}"}}]}}],
+                "usage":{"prompt_tokens":20,"completion_tokens":8}}"#,
+        );
+        assert_tool_answer(&message, "call_2");
+        assert_eq!(stop_reason, Some(StopReason::ToolUse));
+        assert_eq!(usage, tests::usage(20, 8));
+    }
+
+    #[test]
+    fn chatgpt_without_choices_is_a_parse_error() {
+        let config = config(Model::ChatGpt(ChatGptVersion::Gpt5));
+        let body = r#"{"choices":[],"usage":{"prompt_tokens":3,"completion_tokens":0}}"#;
+        assert!(matches!(
+            ModelResponseWrapper::parse_new(body.to_string(), &config),
+            Err(MessageError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn tool_message_text_goes_as_the_users() {
+        let model = Model::ChatGpt(ChatGptVersion::Gpt5);
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "weather".to_string(),
+            arguments: json!({}),
+        };
+        let mut message =
+            Message::from_tool_results(vec![ToolResult::new(&call, "12C, rain".to_string())]);
+        message
+            .content
+            .push(ContentPart::Text("Also, it's Sunday.".to_string()));
+        let out = serde_json::to_value(ChatGptOutMessage::from_message(&message, &model)).unwrap();
+        assert_eq!(
+            out,
+            json!([
+                {"role": "tool", "tool_call_id": "call_1", "content": "12C, rain"},
+                {"role": "user", "content": "Also, it's Sunday."},
+            ])
+        );
+
+        let text_only = Message::new(Role::Tool, vec![ContentPart::Text("done".to_string())]);
+        let out =
+            serde_json::to_value(ChatGptOutMessage::from_message(&text_only, &model)).unwrap();
+        assert_eq!(out, json!([{"role": "user", "content": "done"}]));
+    }
+
+    #[test]
+    fn images_go_out_as_image_url_parts() {
+        let model = Model::ChatGpt(ChatGptVersion::Gpt5);
+        let message = Message::from_user("What's in these?".to_string())
+            .with_image(Image {
+                source: ImageSource::Base64 {
+                    media_type: "image/png".to_string(),
+                    data: "iVBORw0KGgo=".to_string(),
+                },
+            })
+            .with_image(Image::from_url("https://example.com/chart.png".to_string()));
+        let out = serde_json::to_value(ChatGptOutMessage::from_message(&message, &model)).unwrap();
+        assert_eq!(
+            out,
+            json!([{"role": "user", "content": [
+                {"type": "text", "text": "What's in these?"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}},
+                {"type": "image_url", "image_url": {"url": "https://example.com/chart.png"}},
+            ]}])
+        );
+    }
+
+    #[test]
+    fn usage_takes_cached_tokens_out_of_the_prompt_and_keeps_reasoning() {
+        let config = config(Model::ChatGpt(ChatGptVersion::Gpt5));
+        let (_, _, usage) = decode(
+            &config,
+            r#"{"choices":[{"index":0,"finish_reason":"stop","message":{"role":"assistant","content":"Hi"}}],
+                "usage":{"prompt_tokens":320,"completion_tokens":90,
+                "prompt_tokens_details":{"cached_tokens":300},"completion_tokens_details":{"reasoning_tokens":64}}}"#,
+        );
+        assert_eq!(
+            usage,
+            Some(Usage {
+                input_tokens: 20,
+                output_tokens: 90,
+                cache_read_tokens: 300,
+                cache_write_tokens: 0,
+                reasoning_tokens: 64,
+            })
+        );
+    }
+
+    #[test]
+    fn sampling_parameters_are_sent_when_set() {
+        let mut config = config(Model::ChatGpt(ChatGptVersion::Custom(
+            "gpt-4.1".to_string(),
+        )));
+        config.top_p = Some(0.9);
+        config.stop_sequences = vec!["END".to_string()];
+        config.seed = Some(7);
+        config.presence_penalty = Some(0.5);
+        config.frequency_penalty = Some(-0.5);
+        config.logit_bias.insert(42, -100.0);
+        let body = encode(&config, false);
+        for (key, value) in [
+            ("top_p", json!(0.9)),
+            ("stop", json!(["END"])),
+            ("seed", json!(7)),
+            ("presence_penalty", json!(0.5)),
+            ("frequency_penalty", json!(-0.5)),
+            ("logit_bias", json!({"42": -100.0})),
+        ] {
+            assert_eq!(body[key], value, "{key}");
+        }
+        assert!(body.get("top_k").is_none());
+    }
 }
@@ -1,16 +1,24 @@
 use crate::{
-    client::LlmClient,
-    message::{Message, MessageBundle, serde::MessageList},
+    message::{
+        ContentPart, Message, MessageBundle, StopReason, Usage,
+        image::{Image, ImageSource},
+        serde::MessageList,
+    },
+    models::{Model, ModelConfig, Role},
+    tool::ToolCall,
 };
 use serde::{Deserialize, Serialize, Serializer, ser::SerializeStruct};
+use serde_json::Value;
 
 /// Mod purpose:
 /// Specifically implement the guts of a claude interaction according to anthropic's API spec
 
 #[derive(Debug, Clone)]
 pub(crate) struct ClaudeRequest<'a> {
-    pub(crate) client: &'a LlmClient,
+    pub(crate) config: &'a ModelConfig,
+    pub(crate) history: &'a [MessageBundle],
     pub(crate) next: &'a MessageBundle,
+    pub(crate) stream: bool,
 }
 
 impl<'a> Serialize for ClaudeRequest<'a> {
@@ -18,24 +26,57 @@ impl<'a> Serialize for ClaudeRequest<'a> {
     where
         S: Serializer,
     {
+        let config = self.config;
         let mut st = serializer.serialize_struct(
             "ClaudeRequest",
-            4 + usize::from(self.client.config.system_prompt.is_some()),
+            3 + usize::from(config.temperature.is_some())
+                + usize::from(config.top_p.is_some())
+                + usize::from(config.top_k.is_some())
+                + usize::from(!config.stop_sequences.is_empty())
+                + usize::from(config.system_prompt.is_some())
+                + usize::from(self.stream)
+                + usize::from(!config.tools.is_empty()),
         )?;
 
-        st.serialize_field("model", &self.client.config.model.to_model_string())?;
-        st.serialize_field("max_tokens", &self.client.config.max_tokens)?;
-        st.serialize_field("temperature", &self.client.config.temperature)?;
-        if let Some(sys) = &self.client.config.system_prompt {
+        st.serialize_field("model", &self.config.model.to_model_string())?;
+        st.serialize_field("max_tokens", &self.config.max_tokens)?;
+        if let Some(temperature) = config.temperature {
+            st.serialize_field("temperature", &temperature)?;
+        }
+        if let Some(top_p) = config.top_p {
+            st.serialize_field("top_p", &top_p)?;
+        }
+        if let Some(top_k) = config.top_k {
+            st.serialize_field("top_k", &top_k)?;
+        }
+        if !config.stop_sequences.is_empty() {
+            st.serialize_field("stop_sequences", &config.stop_sequences)?;
+        }
+        if let Some(sys) = &self.config.system_prompt {
             st.serialize_field("system", sys)?;
         }
+        if self.stream {
+            st.serialize_field("stream", &true)?;
+        }
+        if !config.tools.is_empty() {
+            let tools: Vec<ClaudeTool> = config
+                .tools
+                .iter()
+                .map(|t| ClaudeTool {
+                    name: &t.name,
+                    description: &t.description,
+                    input_schema: &t.input_schema,
+                })
+                .collect();
+            st.serialize_field("tools", &tools)?;
+        }
 
         st.serialize_field(
             "messages",
             &MessageList {
-                prev: &self.client.message_history,
+                prev: self.history,
                 next: self.next,
-                model: &self.client.config.model,
+                model: &self.config.model,
             },
         )?;
 
@@ -43,24 +84,430 @@ impl<'a> Serialize for ClaudeRequest<'a> {
     }
 }
 
+#[derive(Serialize)]
+struct ClaudeTool<'a> {
+    name: &'a str,
+    description: &'a str,
+    input_schema: &'a Value,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ClaudeOutMessage<'a> {
+    role: String,
+    content: ClaudeOutContent<'a>,
+}
+
+// plain text goes out as a bare string, anything richer needs typed content blocks
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ClaudeOutContent<'a> {
+    Text(&'a str),
+    Blocks(Vec<ClaudeOutBlock<'a>>),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClaudeOutBlock<'a> {
+    Text {
+        text: &'a str,
+    },
+    Image {
+        source: ClaudeImageSource<'a>,
+    },
+    ToolUse {
+        id: &'a str,
+        name: &'a str,
+        input: &'a Value,
+    },
+    ToolResult {
+        tool_use_id: &'a str,
+        content: &'a str,
+        is_error: bool,
+    },
+    Thinking {
+        thinking: &'a str,
+        signature: &'a str,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClaudeImageSource<'a> {
+    Base64 { media_type: &'a str, data: &'a str },
+    Url { url: &'a str },
+}
+
+impl<'a> From<&'a Image> for ClaudeImageSource<'a> {
+    fn from(image: &'a Image) -> Self {
+        match &image.source {
+            ImageSource::Base64 { media_type, data } => {
+                ClaudeImageSource::Base64 { media_type, data }
+            }
+            ImageSource::Url { url, .. } => ClaudeImageSource::Url { url },
+        }
+    }
+}
+
+impl<'a> ClaudeOutMessage<'a> {
+    pub(crate) fn new(message: &'a Message, model: &Model) -> Self {
+        let role = message.role.as_string(model);
+        if let Some(text) = message.as_plain_text() {
+            return ClaudeOutMessage {
+                role,
+                content: ClaudeOutContent::Text(text),
+            };
+        }
+
+        let blocks = message
+            .content
+            .iter()
+            .filter_map(|part| match part {
+                // claude rejects empty text blocks
+                ContentPart::Text(text) if text.is_empty() => None,
+                ContentPart::Text(text) => Some(ClaudeOutBlock::Text { text }),
+                ContentPart::Image(image) => Some(ClaudeOutBlock::Image {
+                    source: image.into(),
+                }),
+                ContentPart::ToolUse(c) => Some(ClaudeOutBlock::ToolUse {
+                    id: &c.id,
+                    name: &c.name,
+                    input: &c.arguments,
+                }),
+                ContentPart::ToolResult(r) => Some(ClaudeOutBlock::ToolResult {
+                    tool_use_id: &r.call_id,
+                    content: &r.content,
+                    is_error: r.is_error,
+                }),
+                // unsigned thinking (e.g. from another provider) can't be replayed to claude
+                ContentPart::Thinking {
+                    text,
+                    signature: Some(signature),
+                } => Some(ClaudeOutBlock::Thinking {
+                    thinking: text,
+                    signature,
+                }),
+                ContentPart::Thinking { .. } => None,
+            })
+            .collect();
+
+        ClaudeOutMessage {
+            role,
+            content: ClaudeOutContent::Blocks(blocks),
+        }
+    }
+}
+
 impl Message {
-    pub(crate) fn from_claude_response(mut value: ClaudeResponse) -> Self {
+    pub(crate) fn from_claude_response(value: ClaudeResponse) -> Self {
         let content = value
             .content
-            .pop()
-            .expect("gotta be something in here")
-            .text;
-        Message::from_ai(content)
+            .into_iter()
+            .filter_map(|block| match block {
+                ClaudeContent::Text { text } => Some(ContentPart::Text(text)),
+                ClaudeContent::ToolUse { id, name, input } => {
+                    Some(ContentPart::ToolUse(ToolCall {
+                        id,
+                        name,
+                        arguments: input,
+                    }))
+                }
+                ClaudeContent::Thinking {
+                    thinking,
+                    signature,
+                } => Some(ContentPart::Thinking {
+                    text: thinking,
+                    signature: Some(signature),
+                }),
+                ClaudeContent::Other => None,
+            })
+            .collect();
+        Message::new(Role::Ai, content)
     }
 }
 
 #[derive(Deserialize, Debug, Clone)]
-pub(crate) struct ClaudeContent {
-    pub(crate) r#type: String,
-    pub(crate) text: String,
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ClaudeContent {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    Thinking {
+        thinking: String,
+        signature: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub(crate) struct ClaudeUsage {
+    pub(crate) input_tokens: u64,
+    pub(crate) output_tokens: u64,
+    pub(crate) cache_creation_input_tokens: Option<u64>,
+    pub(crate) cache_read_input_tokens: Option<u64>,
+}
+
+impl From<ClaudeUsage> for Usage {
+    fn from(value: ClaudeUsage) -> Self {
+        Usage {
+            input_tokens: value.input_tokens,
+            output_tokens: value.output_tokens,
+            cache_read_tokens: value.cache_read_input_tokens.unwrap_or_default(),
+            cache_write_tokens: value.cache_creation_input_tokens.unwrap_or_default(),
+            // claude bills thinking as output without breaking it out
+            reasoning_tokens: 0,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub(crate) struct ClaudeResponse {
     pub(crate) content: Vec<ClaudeContent>,
+    pub(crate) usage: Option<ClaudeUsage>,
+    pub(crate) stop_reason: Option<String>,
+}
+
+impl StopReason {
+    pub(crate) fn from_claude(reason: &str) -> Self {
+        match reason {
+            "end_turn" => StopReason::EndTurn,
+            "max_tokens" => StopReason::MaxTokens,
+            "stop_sequence" => StopReason::StopSequence,
+            "tool_use" => StopReason::ToolUse,
+            "refusal" => StopReason::ContentFilter,
+            other => StopReason::Other(other.to_string()),
+        }
+    }
+}
+
+// {"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ClaudeErrorBody {
+    pub(crate) r#type: String,
+    pub(crate) message: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ClaudeErrorResponse {
+    pub(crate) error: ClaudeErrorBody,
+}
+
+// streaming is a sequence of typed server-sent events, we only care about text & tool use blocks, usage & the terminator
+// (input usage arrives up front on message_start, output usage as running totals on message_delta;
+// a tool use block opens with its id & name, its input follows as fragments of json)
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ClaudeStreamEvent {
+    MessageStart {
+        message: ClaudeStreamMessage,
+    },
+    MessageDelta {
+        #[serde(default)]
+        delta: ClaudeMessageDelta,
+        usage: ClaudeUsage,
+    },
+    ContentBlockStart {
+        index: usize,
+        content_block: ClaudeStreamBlock,
+    },
+    ContentBlockDelta {
+        index: usize,
+        delta: ClaudeStreamDelta,
+    },
+    MessageStop,
+    Error {
+        error: ClaudeStreamError,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ClaudeStreamDelta {
+    TextDelta {
+        text: String,
+    },
+    InputJsonDelta {
+        partial_json: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ClaudeStreamBlock {
+    ToolUse {
+        id: String,
+        name: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct ClaudeMessageDelta {
+    pub(crate) stop_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ClaudeStreamMessage {
+    pub(crate) usage: ClaudeUsage,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct ClaudeStreamError {
+    pub(crate) message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{self, assert_tool_answer, decode, encode, tool_config};
+    use super::*;
+    use crate::message::StopReason;
+    use crate::models::{ClaudeVersion, tests::config};
+    use serde_json::json;
+
+    #[test]
+    fn claude_codec_round_trip() {
+        let config = tool_config(Model::Claude(ClaudeVersion::Sonnet4));
+        assert_eq!(
+            encode(&config, false),
+            json!({
+                "model": "claude-sonnet-4-20250514",
+                "max_tokens": 1024,
+                "system": "Be brief.",
+                "tools": [{
+                    "name": "weather",
+                    "description": "Weather for a city",
+                    "input_schema": {"type": "object", "properties": {"city": {"type": "string"}}},
+                }],
+                "messages": [
+                    {"role": "user", "content": "Weather in Oslo?"},
+                    {"role": "assistant", "content": [
+                        {"type": "text", "text": "Checking."},
+                        {"type": "tool_use", "id": "call_1", "name": "weather", "input": {"city": "Oslo"}},
+                    ]},
+                    {"role": "user", "content": [
+                        {"type": "tool_result", "tool_use_id": "call_1", "content": "12C, rain", "is_error": false},
+                    ]},
+                    {"role": "assistant", "content": "12C and raining."},
+                    {"role": "user", "content": "Thanks"},
+                ],
+            })
+        );
+
+        let (message, stop_reason, usage) = decode(
+            &config,
+            r#"{"id":"msg_1","type":"message","role":"assistant","model":"claude-sonnet-4-20250514",
+                "content":[{"type":"text","text":"Let me check."},{"type":"tool_use","id":"toolu_1","name":"weather","input":{"city":"Bergen"}}],
+                "stop_reason":"tool_use","usage":{"input_tokens":20,"output_tokens":8}}"#,
+        );
+        assert_tool_answer(&message, "toolu_1");
+        assert_eq!(stop_reason, Some(StopReason::ToolUse));
+        assert_eq!(usage, tests::usage(20, 8));
+    }
+
+    #[test]
+    fn content_parts_keep_their_order_and_thinking_its_signature() {
+        let config = config(Model::Claude(ClaudeVersion::Sonnet4));
+        let (message, _, _) = decode(
+            &config,
+            r#"{"id":"msg_1","type":"message","role":"assistant","model":"claude-sonnet-4-20250514",
+                "content":[{"type":"thinking","thinking":"Oslo is in Norway.","signature":"sig_1"},
+                {"type":"text","text":"Checking."},
+                {"type":"tool_use","id":"toolu_1","name":"weather","input":{"city":"Oslo"}}],
+                "stop_reason":"tool_use","usage":{"input_tokens":20,"output_tokens":8}}"#,
+        );
+        assert!(matches!(
+            message.content.as_slice(),
+            [
+                ContentPart::Thinking { text, signature: Some(signature) },
+                ContentPart::Text(_),
+                ContentPart::ToolUse(_),
+            ] if text == "Oslo is in Norway." && signature == "sig_1"
+        ));
+
+        // unsigned thinking, e.g. from another provider, & empty text are left out
+        let mut replayed = message.clone();
+        replayed.content.insert(
+            1,
+            ContentPart::Thinking {
+                text: "unsigned".to_string(),
+                signature: None,
+            },
+        );
+        replayed.content.push(ContentPart::Text(String::new()));
+        let out = serde_json::to_value(ClaudeOutMessage::new(&replayed, &config.model)).unwrap();
+        assert_eq!(
+            out,
+            json!({"role": "assistant", "content": [
+                {"type": "thinking", "thinking": "Oslo is in Norway.", "signature": "sig_1"},
+                {"type": "text", "text": "Checking."},
+                {"type": "tool_use", "id": "toolu_1", "name": "weather", "input": {"city": "Oslo"}},
+            ]})
+        );
+    }
+
+    #[test]
+    fn images_go_out_as_image_blocks() {
+        let model = Model::Claude(ClaudeVersion::Sonnet4);
+        let message = Message::from_user("What's in these?".to_string())
+            .with_image(Image {
+                source: ImageSource::Base64 {
+                    media_type: "image/png".to_string(),
+                    data: "iVBORw0KGgo=".to_string(),
+                },
+            })
+            .with_image(Image::from_url("https://example.com/chart.png".to_string()));
+        let out = serde_json::to_value(ClaudeOutMessage::new(&message, &model)).unwrap();
+        assert_eq!(
+            out,
+            json!({"role": "user", "content": [
+                {"type": "text", "text": "What's in these?"},
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}},
+                {"type": "image", "source": {"type": "url", "url": "https://example.com/chart.png"}},
+            ]})
+        );
+    }
+
+    #[test]
+    fn usage_maps_cache_reads_and_writes() {
+        let config = config(Model::Claude(ClaudeVersion::Sonnet4));
+        let (_, _, usage) = decode(
+            &config,
+            r#"{"content":[{"type":"text","text":"Hi"}],"stop_reason":"end_turn",
+                "usage":{"input_tokens":20,"output_tokens":8,"cache_creation_input_tokens":100,"cache_read_input_tokens":300}}"#,
+        );
+        assert_eq!(
+            usage,
+            Some(Usage {
+                input_tokens: 20,
+                output_tokens: 8,
+                cache_read_tokens: 300,
+                cache_write_tokens: 100,
+                reasoning_tokens: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn sampling_parameters_are_sent_when_set() {
+        let mut config = config(Model::Claude(ClaudeVersion::Sonnet4));
+        config.top_p = Some(0.9);
+        config.top_k = Some(40);
+        config.stop_sequences = vec!["END".to_string()];
+        let body = encode(&config, false);
+        assert_eq!(
+            (&body["top_p"], &body["top_k"], &body["stop_sequences"]),
+            (&json!(0.9), &json!(40), &json!(["END"]))
+        );
+    }
 }
@@ -0,0 +1,542 @@
+use crate::{
+    message::{
+        ContentPart, Message, MessageBundle, StopReason, Usage,
+        image::{Image, ImageSource},
+        serde::MessageList,
+    },
+    models::{Model, ModelConfig, Role},
+    tool::ToolCall,
+};
+use serde::{Deserialize, Serialize, Serializer, ser::SerializeStruct};
+use serde_json::{Value, json};
+
+/// Mod purpose:
+/// Specifically implement the guts of a gemini interaction according to google's generateContent API spec
+
+// no stream flag here, gemini picks streaming by endpoint (see Model::to_stream_target_url)
+#[derive(Debug, Clone)]
+pub(crate) struct GeminiRequest<'a> {
+    pub(crate) config: &'a ModelConfig,
+    pub(crate) history: &'a [MessageBundle],
+    pub(crate) next: &'a MessageBundle,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum GeminiPart<'a> {
+    Text(&'a str),
+    InlineData(GeminiInlineData<'a>),
+    FileData(GeminiFileData<'a>),
+    FunctionCall {
+        name: &'a str,
+        args: &'a Value,
+    },
+    FunctionResponse {
+        name: &'a str,
+        // gemini requires the response to be an object
+        response: Value,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GeminiInlineData<'a> {
+    mime_type: &'a str,
+    data: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GeminiFileData<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mime_type: Option<&'a str>,
+    file_uri: &'a str,
+}
+
+impl<'a> From<&'a Image> for GeminiPart<'a> {
+    fn from(image: &'a Image) -> Self {
+        match &image.source {
+            ImageSource::Base64 { media_type, data } => GeminiPart::InlineData(GeminiInlineData {
+                mime_type: media_type,
+                data,
+            }),
+            ImageSource::Url { url, media_type } => GeminiPart::FileData(GeminiFileData {
+                mime_type: media_type.as_deref(),
+                file_uri: url,
+            }),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct GeminiContent<'a> {
+    role: String,
+    parts: Vec<GeminiPart<'a>>,
+}
+
+impl<'a> GeminiContent<'a> {
+    pub(crate) fn new(message: &'a Message, model: &Model) -> Self {
+        let parts = message
+            .content
+            .iter()
+            .filter_map(|part| match part {
+                ContentPart::Text(text) => Some(GeminiPart::Text(text)),
+                ContentPart::Image(image) => Some(image.into()),
+                ContentPart::ToolUse(c) => Some(GeminiPart::FunctionCall {
+                    name: &c.name,
+                    args: &c.arguments,
+                }),
+                ContentPart::ToolResult(r) => {
+                    let response = match r.is_error {
+                        true => json!({ "error": r.content }),
+                        false => json!({ "content": r.content }),
+                    };
+                    Some(GeminiPart::FunctionResponse {
+                        name: &r.name,
+                        response,
+                    })
+                }
+                // thought summaries are output only
+                ContentPart::Thinking { .. } => None,
+            })
+            .collect();
+
+        GeminiContent {
+            role: message.role.as_string(model),
+            parts,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct GeminiSystemInstruction<'a> {
+    parts: [GeminiPart<'a>; 1],
+}
+
+#[derive(Serialize)]
+struct GeminiFunctionDeclaration<'a> {
+    name: &'a str,
+    description: &'a str,
+    parameters: &'a Value,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiTool<'a> {
+    function_declarations: Vec<GeminiFunctionDeclaration<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiGenerationConfig<'a> {
+    max_output_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<u32>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop_sequences: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    candidate_count: Option<usize>,
+}
+
+impl<'a> Serialize for GeminiRequest<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let config = self.config;
+        let mut st = serializer.serialize_struct(
+            "GeminiRequest",
+            2 + usize::from(config.system_prompt.is_some()) + usize::from(!config.tools.is_empty()),
+        )?;
+
+        // gemini carries the model in the url, and the system prompt outside of the contents
+        if let Some(sys) = &config.system_prompt {
+            st.serialize_field(
+                "systemInstruction",
+                &GeminiSystemInstruction {
+                    parts: [GeminiPart::Text(sys)],
+                },
+            )?;
+        }
+        if !config.tools.is_empty() {
+            let declarations = config
+                .tools
+                .iter()
+                .map(|t| GeminiFunctionDeclaration {
+                    name: &t.name,
+                    description: &t.description,
+                    parameters: &t.input_schema,
+                })
+                .collect();
+            st.serialize_field(
+                "tools",
+                &[GeminiTool {
+                    function_declarations: declarations,
+                }],
+            )?;
+        }
+
+        st.serialize_field(
+            "contents",
+            &MessageList {
+                prev: self.history,
+                next: self.next,
+                model: &config.model,
+            },
+        )?;
+
+        st.serialize_field(
+            "generationConfig",
+            &GeminiGenerationConfig {
+                max_output_tokens: config.max_tokens,
+                temperature: config.temperature,
+                top_p: config.top_p,
+                top_k: config.top_k,
+                stop_sequences: &config.stop_sequences,
+                seed: config.seed,
+                presence_penalty: config.presence_penalty,
+                frequency_penalty: config.frequency_penalty,
+                candidate_count: config.candidates,
+            },
+        )?;
+
+        st.end()
+    }
+}
+
+impl Message {
+    // parse_new turns away responses without candidates, so there's always one here
+    pub(crate) fn from_gemini_response(mut value: GeminiResponse) -> Self {
+        value
+            .candidates
+            .pop()
+            .map(Message::from_gemini_candidate)
+            .unwrap_or_else(|| Message::new(Role::Ai, Vec::new()))
+    }
+
+    pub(crate) fn from_gemini_candidate(candidate: GeminiCandidate) -> Self {
+        let parts = candidate.content.parts;
+        let mut content = Vec::new();
+        let mut calls = 0;
+        for part in parts {
+            match (part.text, part.thought) {
+                (Some(text), Some(true)) => content.push(ContentPart::Thinking {
+                    text,
+                    signature: part.thought_signature,
+                }),
+                (Some(text), _) => content.push(ContentPart::Text(text)),
+                (None, _) => (),
+            }
+            if let Some(call) = part.function_call {
+                // gemini doesn't id its calls, results are matched back up by name
+                content.push(ContentPart::ToolUse(ToolCall {
+                    id: ToolCall::positional_id(&call.name, calls),
+                    name: call.name,
+                    arguments: call.args,
+                }));
+                calls += 1;
+            }
+        }
+        Message::new(Role::Ai, content)
+    }
+}
+
+impl GeminiResponse {
+    // streamed chunks only carry a finish reason on the last one
+    pub(crate) fn stop_reason(&self) -> Option<StopReason> {
+        self.candidates.last()?.stop_reason()
+    }
+
+    // the function calls of the candidate into_text reads, left without ids:
+    // a streamed chunk doesn't know where its calls fall in the response, the stream assembler numbers them
+    pub(crate) fn tool_calls(&self) -> Vec<ToolCall> {
+        self.candidates
+            .last()
+            .map(|c| c.content.parts.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|p| p.function_call.clone())
+            .map(|call| ToolCall {
+                id: String::new(),
+                name: call.name,
+                arguments: call.args,
+            })
+            .collect()
+    }
+
+    // gemini can split a single answer across several text parts
+    // streamed chunks share the response shape, so this is used for both
+    pub(crate) fn into_text(mut self) -> Option<String> {
+        let text = self
+            .candidates
+            .pop()?
+            .content
+            .parts
+            .into_iter()
+            .filter(|p| p.thought != Some(true))
+            .filter_map(|p| p.text)
+            .collect::<Vec<String>>()
+            .concat();
+        Some(text)
+    }
+}
+
+impl StopReason {
+    // gemini doesn't tell a stop sequence apart from a natural end, both are STOP
+    pub(crate) fn from_gemini(reason: &str) -> Self {
+        match reason {
+            "STOP" => StopReason::EndTurn,
+            "MAX_TOKENS" => StopReason::MaxTokens,
+            "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII"
+            | "IMAGE_SAFETY" => StopReason::ContentFilter,
+            other => StopReason::Other(other.to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct GeminiFunctionCall {
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) args: Value,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GeminiResponsePart {
+    pub(crate) text: Option<String>,
+    pub(crate) thought: Option<bool>,
+    pub(crate) thought_signature: Option<String>,
+    pub(crate) function_call: Option<GeminiFunctionCall>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct GeminiCandidateContent {
+    #[serde(default)]
+    pub(crate) parts: Vec<GeminiResponsePart>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GeminiCandidate {
+    #[serde(default)]
+    pub(crate) content: GeminiCandidateContent,
+    pub(crate) finish_reason: Option<String>,
+    // which of the requested candidateCount this is, omitted when only one was asked for
+    #[serde(default)]
+    pub(crate) index: usize,
+}
+
+impl GeminiCandidate {
+    pub(crate) fn stop_reason(&self) -> Option<StopReason> {
+        let reason = StopReason::from_gemini(self.finish_reason.as_deref()?);
+        // gemini finishes a turn of function calls with a plain STOP
+        let calls_tools = self.content.parts.iter().any(|p| p.function_call.is_some());
+        Some(match reason {
+            StopReason::EndTurn if calls_tools => StopReason::ToolUse,
+            reason => reason,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct GeminiUsage {
+    pub(crate) prompt_token_count: u64,
+    pub(crate) candidates_token_count: u64,
+    pub(crate) cached_content_token_count: u64,
+    pub(crate) thoughts_token_count: u64,
+}
+
+impl From<GeminiUsage> for Usage {
+    fn from(value: GeminiUsage) -> Self {
+        // cached tokens are counted within the prompt, thoughts are billed as output but counted apart from candidates
+        Usage {
+            input_tokens: value
+                .prompt_token_count
+                .saturating_sub(value.cached_content_token_count),
+            output_tokens: value.candidates_token_count + value.thoughts_token_count,
+            cache_read_tokens: value.cached_content_token_count,
+            cache_write_tokens: 0,
+            reasoning_tokens: value.thoughts_token_count,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GeminiResponse {
+    #[serde(default)]
+    pub(crate) candidates: Vec<GeminiCandidate>,
+    pub(crate) usage_metadata: Option<GeminiUsage>,
+    /// a blocked prompt comes back with this & no candidates
+    pub(crate) prompt_feedback: Option<GeminiPromptFeedback>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GeminiPromptFeedback {
+    pub(crate) block_reason: Option<String>,
+}
+
+// {"error":{"code":429,"message":"...","status":"RESOURCE_EXHAUSTED"}}
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct GeminiErrorBody {
+    pub(crate) code: Option<u16>,
+    pub(crate) message: String,
+    pub(crate) status: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct GeminiErrorResponse {
+    pub(crate) error: GeminiErrorBody,
+}
+
+// an error can also arrive mid-stream in place of a chunk
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub(crate) enum GeminiStreamChunk {
+    Error(GeminiErrorResponse),
+    Chunk(GeminiResponse),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{self, assert_tool_answer, decode, encode, tool_config};
+    use super::super::{ModelRequestWrapper, ModelResponseWrapper};
+    use super::*;
+    use crate::message::{MessageError, MessageMetadata};
+    use crate::models::{GeminiVersion, tests::config};
+
+    #[test]
+    fn gemini_codec_round_trip() {
+        let config = tool_config(Model::Gemini(GeminiVersion::Gemini25Flash));
+        assert_eq!(
+            encode(&config, false),
+            json!({
+                "systemInstruction": {"parts": [{"text": "Be brief."}]},
+                "generationConfig": {"maxOutputTokens": 1024},
+                "tools": [{"functionDeclarations": [{
+                    "name": "weather",
+                    "description": "Weather for a city",
+                    "parameters": {"type": "object", "properties": {"city": {"type": "string"}}},
+                }]}],
+                "contents": [
+                    {"role": "user", "parts": [{"text": "Weather in Oslo?"}]},
+                    {"role": "model", "parts": [
+                        {"text": "Checking."},
+                        {"functionCall": {"name": "weather", "args": {"city": "Oslo"}}},
+                    ]},
+                    {"role": "user", "parts": [
+                        {"functionResponse": {"name": "weather", "response": {"content": "12C, rain"}}},
+                    ]},
+                    {"role": "model", "parts": [{"text": "12C and raining."}]},
+                    {"role": "user", "parts": [{"text": "Thanks"}]},
+                ],
+            })
+        );
+
+        let (message, stop_reason, usage) = decode(
+            &config,
+            r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Let me check."},{"functionCall":{"name":"weather","args":{"city":"Bergen"}}}]},
+                "finishReason":"STOP"}],"usageMetadata":{"promptTokenCount":20,"candidatesTokenCount":8}}"#,
+        );
+        assert_tool_answer(&message, "weather-0");
+        assert_eq!(stop_reason, Some(StopReason::ToolUse));
+        assert_eq!(usage, tests::usage(20, 8));
+    }
+
+    #[test]
+    fn gemini_blocked_prompt_is_a_parse_error() {
+        let config = config(Model::Gemini(GeminiVersion::Gemini25Flash));
+        let body =
+            r#"{"promptFeedback":{"blockReason":"SAFETY"},"usageMetadata":{"promptTokenCount":7}}"#;
+        match ModelResponseWrapper::parse_new(body.to_string(), &config) {
+            Err(MessageError::Parse(e)) => assert!(e.contains("prompt blocked: SAFETY"), "{e}"),
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parallel_calls_of_one_tool_get_distinct_ids() {
+        let config = config(Model::Gemini(GeminiVersion::Gemini25Flash));
+        let (message, _, _) = decode(
+            &config,
+            r#"{"candidates":[{"content":{"role":"model","parts":[
+                {"functionCall":{"name":"weather","args":{"city":"Oslo"}}},
+                {"functionCall":{"name":"weather","args":{"city":"Bergen"}}}]},"finishReason":"STOP"}]}"#,
+        );
+        let ids: Vec<&str> = message
+            .tool_calls()
+            .into_iter()
+            .map(|c| c.id.as_str())
+            .collect();
+        assert_eq!(ids, ["weather-0", "weather-1"]);
+    }
+
+    #[test]
+    fn versionless_gemini_is_refused() {
+        let config = config(Model::Gemini(GeminiVersion::None));
+        let next = MessageBundle::new(
+            Message::from_user("Hi".to_string()),
+            MessageMetadata::new(&config),
+        );
+        assert!(matches!(
+            ModelRequestWrapper::new(&config, &[], &next, false),
+            Err(MessageError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn usage_takes_cached_tokens_out_of_the_prompt_and_bills_thoughts_as_output() {
+        let config = config(Model::Gemini(GeminiVersion::Gemini25Flash));
+        let (_, _, usage) = decode(
+            &config,
+            r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Hi"}]},"finishReason":"STOP"}],
+                "usageMetadata":{"promptTokenCount":320,"candidatesTokenCount":26,"cachedContentTokenCount":300,"thoughtsTokenCount":64}}"#,
+        );
+        assert_eq!(
+            usage,
+            Some(Usage {
+                input_tokens: 20,
+                output_tokens: 90,
+                cache_read_tokens: 300,
+                cache_write_tokens: 0,
+                reasoning_tokens: 64,
+            })
+        );
+    }
+
+    #[test]
+    fn sampling_parameters_go_in_the_generation_config() {
+        let mut config = config(Model::Gemini(GeminiVersion::Gemini25Flash));
+        config.top_p = Some(0.9);
+        config.top_k = Some(40);
+        config.stop_sequences = vec!["END".to_string()];
+        config.seed = Some(7);
+        config.presence_penalty = Some(0.5);
+        config.frequency_penalty = Some(-0.5);
+        assert_eq!(
+            encode(&config, false)["generationConfig"],
+            json!({
+                "maxOutputTokens": 1024,
+                "topP": 0.9,
+                "topK": 40,
+                "stopSequences": ["END"],
+                "seed": 7,
+                "presencePenalty": 0.5,
+                "frequencyPenalty": -0.5,
+            })
+        );
+    }
+}
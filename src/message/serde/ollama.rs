@@ -0,0 +1,344 @@
+use crate::{
+    message::{
+        ContentPart, Message, MessageBundle, StopReason, Usage, image::ImageSource,
+        serde::MessageList,
+    },
+    models::{Model, ModelConfig, Role},
+    tool::ToolCall,
+};
+use serde::{Deserialize, Serialize, Serializer, ser::SerializeStruct};
+use serde_json::Value;
+
+/// Mod purpose:
+/// Specifically implement the guts of an ollama interaction according to ollama's native /api/chat spec
+
+#[derive(Debug, Clone)]
+pub(crate) struct OllamaRequest<'a> {
+    pub(crate) config: &'a ModelConfig,
+    pub(crate) history: &'a [MessageBundle],
+    pub(crate) next: &'a MessageBundle,
+    pub(crate) stream: bool,
+}
+
+impl<'a> Serialize for OllamaRequest<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let config = self.config;
+        let mut st = serializer
+            .serialize_struct("OllamaRequest", 4 + usize::from(!config.tools.is_empty()))?;
+
+        st.serialize_field("model", &config.model.to_model_string())?;
+        // ollama streams unless told otherwise
+        st.serialize_field("stream", &self.stream)?;
+        st.serialize_field(
+            "options",
+            &OllamaOptions {
+                temperature: config.temperature,
+                num_predict: config.max_tokens,
+                top_p: config.top_p,
+                top_k: config.top_k,
+                stop: &config.stop_sequences,
+                seed: config.seed,
+                presence_penalty: config.presence_penalty,
+                frequency_penalty: config.frequency_penalty,
+            },
+        )?;
+        if !config.tools.is_empty() {
+            let tools: Vec<OllamaTool> = config
+                .tools
+                .iter()
+                .map(|t| OllamaTool {
+                    r#type: "function",
+                    function: OllamaFunction {
+                        name: &t.name,
+                        description: &t.description,
+                        parameters: &t.input_schema,
+                    },
+                })
+                .collect();
+            st.serialize_field("tools", &tools)?;
+        }
+
+        st.serialize_field(
+            "messages",
+            &MessageList {
+                prev: self.history,
+                next: self.next,
+                model: &config.model,
+            },
+        )?;
+
+        st.end()
+    }
+}
+
+#[derive(Serialize)]
+struct OllamaOptions<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    num_predict: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<u32>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct OllamaFunction<'a> {
+    name: &'a str,
+    description: &'a str,
+    parameters: &'a Value,
+}
+
+#[derive(Serialize)]
+struct OllamaTool<'a> {
+    r#type: &'static str,
+    function: OllamaFunction<'a>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct OllamaOutFunctionCall<'a> {
+    name: &'a str,
+    // unlike openai, ollama takes arguments as a json object
+    arguments: &'a Value,
+}
+
+#[derive(Serialize)]
+pub(crate) struct OllamaOutToolCall<'a> {
+    function: OllamaOutFunctionCall<'a>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct OllamaOutMessage<'a> {
+    role: String,
+    content: String,
+    // base64 only, url images are rejected before the request is built
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OllamaOutToolCall<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_name: Option<&'a str>,
+}
+
+impl<'a> OllamaOutMessage<'a> {
+    // one message per tool result, same as openai; thinking isn't replayed
+    pub(crate) fn from_message(message: &'a Message, model: &Model) -> Vec<Self> {
+        let mut out: Vec<OllamaOutMessage> = message
+            .tool_results()
+            .into_iter()
+            .map(|r| OllamaOutMessage {
+                role: Role::Tool.as_string(model),
+                content: r.content.clone(),
+                images: Vec::new(),
+                tool_calls: Vec::new(),
+                tool_name: Some(&r.name),
+            })
+            .collect();
+
+        let images: Vec<&str> = message
+            .content
+            .iter()
+            .filter_map(|part| match part {
+                ContentPart::Image(image) => match &image.source {
+                    ImageSource::Base64 { data, .. } => Some(data.as_str()),
+                    ImageSource::Url { .. } => None,
+                },
+                _ => None,
+            })
+            .collect();
+        let tool_calls: Vec<OllamaOutToolCall> = message
+            .tool_calls()
+            .into_iter()
+            .map(|c| OllamaOutToolCall {
+                function: OllamaOutFunctionCall {
+                    name: &c.name,
+                    arguments: &c.arguments,
+                },
+            })
+            .collect();
+        let text = message.text();
+
+        if !text.is_empty() || !images.is_empty() || !tool_calls.is_empty() {
+            out.push(OllamaOutMessage {
+                role: message.role.as_string(model),
+                content: text,
+                images,
+                tool_calls,
+                tool_name: None,
+            });
+        }
+        out
+    }
+}
+
+impl Message {
+    pub(crate) fn from_ollama_response(value: OllamaResponse) -> Self {
+        let message = value.message;
+        let thinking =
+            message
+                .thinking
+                .filter(|t| !t.is_empty())
+                .map(|text| ContentPart::Thinking {
+                    text,
+                    signature: None,
+                });
+        let text = Some(message.content)
+            .filter(|t| !t.is_empty())
+            .map(ContentPart::Text);
+        // ollama doesn't id tool calls, one is made from the name & position (same as gemini)
+        let tool_calls = message.tool_calls.into_iter().enumerate().map(|(i, c)| {
+            ContentPart::ToolUse(ToolCall {
+                id: ToolCall::positional_id(&c.function.name, i),
+                name: c.function.name,
+                arguments: c.function.arguments,
+            })
+        });
+        let content = thinking.into_iter().chain(text).chain(tool_calls).collect();
+        Message::new(Role::Ai, content)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct OllamaFunctionCall {
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) arguments: Value,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct OllamaToolCall {
+    pub(crate) function: OllamaFunctionCall,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub(crate) struct OllamaResponseMessage {
+    pub(crate) content: String,
+    pub(crate) thinking: Option<String>,
+    pub(crate) tool_calls: Vec<OllamaToolCall>,
+}
+
+// the same shape comes back whole or as ndjson stream chunks, counts only arrive once done
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct OllamaResponse {
+    #[serde(default)]
+    pub(crate) message: OllamaResponseMessage,
+    #[serde(default)]
+    pub(crate) done: bool,
+    pub(crate) prompt_eval_count: Option<u64>,
+    pub(crate) eval_count: Option<u64>,
+    pub(crate) done_reason: Option<String>,
+}
+
+impl OllamaResponse {
+    // only the final (done) chunk carries a reason; tool calls end with a plain "stop"
+    pub(crate) fn stop_reason(&self) -> Option<StopReason> {
+        let reason = match self.done_reason.as_deref()? {
+            "stop" if !self.message.tool_calls.is_empty() => StopReason::ToolUse,
+            "stop" => StopReason::EndTurn,
+            "length" => StopReason::MaxTokens,
+            other => StopReason::Other(other.to_string()),
+        };
+        Some(reason)
+    }
+
+    pub(crate) fn usage(&self) -> Option<Usage> {
+        if self.prompt_eval_count.is_none() && self.eval_count.is_none() {
+            return None;
+        }
+        Some(Usage {
+            input_tokens: self.prompt_eval_count.unwrap_or_default(),
+            output_tokens: self.eval_count.unwrap_or_default(),
+            ..Default::default()
+        })
+    }
+}
+
+// {"error":"model \"llama9\" not found, try pulling it first"}
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct OllamaErrorResponse {
+    pub(crate) error: String,
+}
+
+// an error can also arrive mid-stream in place of a chunk
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub(crate) enum OllamaStreamChunk {
+    Error(OllamaErrorResponse),
+    Chunk(OllamaResponse),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{self, assert_tool_answer, decode, encode, tool_config};
+    use super::*;
+    use crate::models::tests::config;
+    use serde_json::json;
+
+    #[test]
+    fn ollama_codec_round_trip() {
+        let config = tool_config(Model::Ollama("llama3.2".to_string()));
+        assert_eq!(
+            encode(&config, true),
+            json!({
+                "model": "llama3.2",
+                "stream": false,
+                "options": {"num_predict": 1024},
+                "tools": [{
+                    "type": "function",
+                    "function": {
+                        "name": "weather",
+                        "description": "Weather for a city",
+                        "parameters": {"type": "object", "properties": {"city": {"type": "string"}}},
+                    },
+                }],
+                "messages": [
+                    {"role": "system", "content": "Be brief."},
+                    {"role": "user", "content": "Weather in Oslo?"},
+                    {"role": "assistant", "content": "Checking.", "tool_calls": [
+                        {"function": {"name": "weather", "arguments": {"city": "Oslo"}}},
+                    ]},
+                    {"role": "tool", "tool_name": "weather", "content": "12C, rain"},
+                    {"role": "assistant", "content": "12C and raining."},
+                    {"role": "user", "content": "Thanks"},
+                ],
+            })
+        );
+
+        let (message, stop_reason, usage) = decode(
+            &config,
+            r#"{"model":"llama3.2","message":{"role":"assistant","content":"Let me check.",
+                "tool_calls":[{"function":{"name":"weather","arguments":{"city":"Bergen"}}}]},
+                "done":true,"done_reason":"stop","prompt_eval_count":20,"eval_count":8}"#,
+        );
+        assert_tool_answer(&message, "weather-0");
+        assert_eq!(stop_reason, Some(StopReason::ToolUse));
+        assert_eq!(usage, tests::usage(20, 8));
+    }
+
+    #[test]
+    fn ollama_sampling_options_go_in_options() {
+        let mut config = config(Model::Ollama("llama3.2".to_string()));
+        config.temperature = Some(0.2);
+        config.top_k = Some(40);
+        config.stop_sequences = vec!["END".to_string()];
+        config.seed = Some(7);
+        let body = encode(&config, false);
+        assert_eq!(
+            body["options"],
+            json!({"temperature": 0.2, "num_predict": 1024, "top_k": 40, "stop": ["END"], "seed": 7})
+        );
+        assert!(body.get("tools").is_none());
+    }
+}
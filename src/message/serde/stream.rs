@@ -0,0 +1,316 @@
+use futures::{Stream, StreamExt, stream};
+use reqwest::Error;
+
+use crate::{
+    message::{StopReason, StreamDelta, Usage},
+    models::Model,
+    tool::ToolCall,
+};
+
+use super::{
+    MessageError,
+    chatgpt::{CHATGPT_STREAM_DONE, ChatGptStreamChunk},
+    claude::{ClaudeStreamBlock, ClaudeStreamDelta, ClaudeStreamEvent},
+    custom_model,
+    gemini::GeminiStreamChunk,
+    ollama::OllamaStreamChunk,
+};
+
+/// Mod purpose:
+/// Turn a streamed response body into provider-neutral deltas.
+/// All providers we speak to stream single-line json payloads, so a body is split into lines first & each line decoded on its own.
+
+#[derive(Debug, Clone)]
+pub enum StreamFrame {
+    Delta(StreamDelta),
+    /// running totals, possibly partial
+    Usage(Usage),
+    /// why generation ended, ahead of Done
+    Stop(StopReason),
+    /// a tool call that arrived whole, with an empty id when the provider doesn't assign one (the stream assembler does)
+    ToolCall(ToolCall),
+    /// part of a tool call arriving in pieces, fragments with the same index are one call:
+    /// id & name are set on (at least) the first, arguments are json text to append
+    ToolCallDelta {
+        index: usize,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: String,
+    },
+    Done,
+    Error(String),
+}
+
+/// Splits a chunked body on newlines, chunks are not guaranteed to end on a line boundary
+pub(crate) fn into_lines<S, B>(bytes: S) -> impl Stream<Item = Result<String, Error>>
+where
+    S: Stream<Item = Result<B, Error>> + Unpin,
+    B: AsRef<[u8]>,
+{
+    stream::unfold(
+        (bytes, Vec::<u8>::new(), false),
+        |(mut bytes, mut buffer, mut exhausted)| async move {
+            loop {
+                if let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=pos).collect();
+                    let line = String::from_utf8_lossy(&line).trim_end().to_string();
+                    return Some((Ok(line), (bytes, buffer, exhausted)));
+                }
+                if exhausted {
+                    // flush a trailing line without a newline terminator
+                    if buffer.is_empty() {
+                        return None;
+                    }
+                    let line = String::from_utf8_lossy(&buffer).trim_end().to_string();
+                    buffer.clear();
+                    return Some((Ok(line), (bytes, buffer, exhausted)));
+                }
+                match bytes.next().await {
+                    Some(Ok(chunk)) => buffer.extend_from_slice(chunk.as_ref()),
+                    Some(Err(e)) => return Some((Err(e), (bytes, buffer, exhausted))),
+                    None => exhausted = true,
+                }
+            }
+        },
+    )
+}
+
+/// Decodes a single server-sent event line for the given model; a line may carry several frames (e.g. text & usage)
+pub(crate) fn decode_stream_line(
+    line: &str,
+    model: &Model,
+) -> Result<Vec<StreamFrame>, MessageError> {
+    let data = match model {
+        // ollama streams newline delimited json rather than server-sent events
+        Model::Ollama(_) => line.trim(),
+        // event names, comments & keep-alive blank lines carry nothing we don't also get in the data payload
+        _ => match line.strip_prefix("data:") {
+            Some(data) => data.trim(),
+            None => return Ok(vec![]),
+        },
+    };
+    if data.is_empty() {
+        return Ok(vec![]);
+    }
+
+    match model {
+        Model::Claude(_) => {
+            let event = serde_json::from_str::<ClaudeStreamEvent>(data)
+                .map_err(|e| MessageError::Parse(e.to_string()))?;
+            Ok(match event {
+                ClaudeStreamEvent::MessageStart { message } => {
+                    vec![StreamFrame::Usage(message.usage.into())]
+                }
+                ClaudeStreamEvent::MessageDelta { delta, usage } => {
+                    let stop = delta
+                        .stop_reason
+                        .as_deref()
+                        .map(|r| StreamFrame::Stop(StopReason::from_claude(r)));
+                    stop.into_iter()
+                        .chain([StreamFrame::Usage(usage.into())])
+                        .collect()
+                }
+                ClaudeStreamEvent::ContentBlockStart {
+                    index,
+                    content_block: ClaudeStreamBlock::ToolUse { id, name },
+                } => vec![StreamFrame::ToolCallDelta {
+                    index,
+                    id: Some(id),
+                    name: Some(name),
+                    arguments: String::new(),
+                }],
+                ClaudeStreamEvent::ContentBlockDelta {
+                    delta: ClaudeStreamDelta::TextDelta { text },
+                    ..
+                } => vec![StreamFrame::Delta(StreamDelta::Text(text))],
+                ClaudeStreamEvent::ContentBlockDelta {
+                    index,
+                    delta: ClaudeStreamDelta::InputJsonDelta { partial_json },
+                } => vec![StreamFrame::ToolCallDelta {
+                    index,
+                    id: None,
+                    name: None,
+                    arguments: partial_json,
+                }],
+                ClaudeStreamEvent::MessageStop => vec![StreamFrame::Done],
+                ClaudeStreamEvent::Error { error } => vec![StreamFrame::Error(error.message)],
+                _ => vec![],
+            })
+        }
+        Model::ChatGpt(_) | Model::OpenAiCompatible { .. } | Model::AzureOpenAi { .. } => {
+            if data == CHATGPT_STREAM_DONE {
+                return Ok(vec![StreamFrame::Done]);
+            }
+            let chunk = serde_json::from_str::<ChatGptStreamChunk>(data)
+                .map_err(|e| MessageError::Parse(e.to_string()))?;
+            let (text, tool_calls, stop) = match chunk.choices.into_iter().next() {
+                Some(choice) => (
+                    choice.delta.content,
+                    choice.delta.tool_calls,
+                    choice.finish_reason,
+                ),
+                None => (None, Vec::new(), None),
+            };
+            let text = text
+                .filter(|t| !t.is_empty())
+                .map(|t| StreamFrame::Delta(StreamDelta::Text(t)));
+            let tool_calls = tool_calls.into_iter().map(|c| {
+                let function = c.function.unwrap_or_default();
+                StreamFrame::ToolCallDelta {
+                    index: c.index,
+                    id: c.id,
+                    name: function.name,
+                    arguments: function.arguments.unwrap_or_default(),
+                }
+            });
+            let stop = stop.map(|r| StreamFrame::Stop(StopReason::from_chatgpt(&r)));
+            let usage = chunk.usage.map(|u| StreamFrame::Usage(u.into()));
+            Ok(text
+                .into_iter()
+                .chain(tool_calls)
+                .chain(stop)
+                .chain(usage)
+                .collect())
+        }
+        // gemini has no terminator event, the chunk carrying a finish reason is the last one
+        Model::Gemini(_) => {
+            let mut chunk = match serde_json::from_str::<GeminiStreamChunk>(data)
+                .map_err(|e| MessageError::Parse(e.to_string()))?
+            {
+                GeminiStreamChunk::Error(e) => {
+                    return Ok(vec![StreamFrame::Error(e.error.message)]);
+                }
+                GeminiStreamChunk::Chunk(chunk) => chunk,
+            };
+            let usage = chunk
+                .usage_metadata
+                .take()
+                .map(|u| StreamFrame::Usage(u.into()));
+            let stop = chunk.stop_reason().map(StreamFrame::Stop);
+            let done = stop.is_some().then_some(StreamFrame::Done);
+            // gemini streams function calls whole
+            let tool_calls: Vec<StreamFrame> = chunk
+                .tool_calls()
+                .into_iter()
+                .map(StreamFrame::ToolCall)
+                .collect();
+            let text = chunk
+                .into_text()
+                .filter(|t| !t.is_empty())
+                .map(|t| StreamFrame::Delta(StreamDelta::Text(t)));
+            Ok(text
+                .into_iter()
+                .chain(tool_calls)
+                .chain(stop)
+                .chain(usage)
+                .chain(done)
+                .collect())
+        }
+        Model::Ollama(_) => {
+            let chunk = match serde_json::from_str::<OllamaStreamChunk>(data)
+                .map_err(|e| MessageError::Parse(e.to_string()))?
+            {
+                OllamaStreamChunk::Error(e) => return Ok(vec![StreamFrame::Error(e.error)]),
+                OllamaStreamChunk::Chunk(chunk) => chunk,
+            };
+            let usage = chunk.usage().map(StreamFrame::Usage);
+            let stop = chunk.stop_reason().map(StreamFrame::Stop);
+            let done = chunk.done.then_some(StreamFrame::Done);
+            // as with gemini, calls arrive whole & without ids
+            let tool_calls = chunk.message.tool_calls.into_iter().map(|c| {
+                StreamFrame::ToolCall(ToolCall {
+                    id: String::new(),
+                    name: c.function.name,
+                    arguments: c.function.arguments,
+                })
+            });
+            let text = Some(chunk.message.content)
+                .filter(|t| !t.is_empty())
+                .map(|t| StreamFrame::Delta(StreamDelta::Text(t)));
+            Ok(text
+                .into_iter()
+                .chain(tool_calls)
+                .chain(stop)
+                .chain(usage)
+                .chain(done)
+                .collect())
+        }
+        Model::Custom { .. } => Err(custom_model(model)),
+        #[cfg(feature = "dev-tools")]
+        Model::None => panic!("dev tools only"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ClaudeVersion, GeminiVersion};
+
+    #[test]
+    fn claude_tool_use_blocks_decode_to_fragments() {
+        let model = Model::Claude(ClaudeVersion::Sonnet4);
+        let start = r#"data: {"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_1","name":"get_weather","input":{}}}"#;
+        let delta = r#"data: {"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"city\":"}}"#;
+
+        match decode_stream_line(start, &model).unwrap().as_slice() {
+            [
+                StreamFrame::ToolCallDelta {
+                    index: 1,
+                    id: Some(id),
+                    name: Some(name),
+                    arguments,
+                },
+            ] => {
+                assert_eq!(
+                    (id.as_str(), name.as_str(), arguments.as_str()),
+                    ("toolu_1", "get_weather", "")
+                );
+            }
+            other => panic!("unexpected frames {other:?}"),
+        }
+        match decode_stream_line(delta, &model).unwrap().as_slice() {
+            [
+                StreamFrame::ToolCallDelta {
+                    index: 1,
+                    id: None,
+                    name: None,
+                    arguments,
+                },
+            ] => {
+                assert_eq!(arguments, "{\"city\":");
+            }
+            other => panic!("unexpected frames {other:?}"),
+        }
+    }
+
+    #[test]
+    fn gemini_chunk_with_a_finish_reason_ends_the_stream() {
+        let model = Model::Gemini(GeminiVersion::Gemini25Flash);
+        let middle =
+            r#"data: {"candidates":[{"content":{"role":"model","parts":[{"text":"Hel"}]}}]}"#;
+        let last = r#"data: {"candidates":[{"content":{"role":"model","parts":[{"text":"lo"}]},"finishReason":"STOP"}]}"#;
+
+        let frames = decode_stream_line(middle, &model).unwrap();
+        assert!(!frames.iter().any(|f| matches!(f, StreamFrame::Done)));
+        assert!(matches!(
+            decode_stream_line(last, &model).unwrap().as_slice(),
+            [
+                StreamFrame::Delta(StreamDelta::Text(_)),
+                StreamFrame::Stop(StopReason::EndTurn),
+                StreamFrame::Done,
+            ]
+        ));
+    }
+
+    #[test]
+    fn gemini_error_line_is_an_error_frame() {
+        let line = r#"data: {"error":{"code":503,"message":"The model is overloaded.","status":"UNAVAILABLE"}}"#;
+        match decode_stream_line(line, &Model::Gemini(GeminiVersion::Gemini25Flash))
+            .unwrap()
+            .as_slice()
+        {
+            [StreamFrame::Error(message)] => assert_eq!(message, "The model is overloaded."),
+            other => panic!("unexpected frames {other:?}"),
+        }
+    }
+}
@@ -3,43 +3,74 @@ pub mod maintenance;
 #[cfg(feature = "dev-tools")]
 use strum_macros::EnumIter;
 
-use std::{error::Error, fmt::Display};
+use std::{collections::BTreeMap, error::Error, fmt::Display, str::FromStr};
 
 use secrecy::SecretString;
+use serde::{Deserialize, Serialize};
 
-use crate::environment::get_api_key;
+use crate::{
+    environment::{BASE_URL_AZURE_OPENAI, get_api_key, get_base_url},
+    tool::ToolDefinition,
+};
 
 /// Mod purpose:
 /// Single enumeration place for all specific implementations by model
 /// The goal is to add/extend support for any support by being able to only touch this file, add environment.rs, & add an llm/newthing for it.
 
+#[allow(clippy::empty_line_after_doc_comments)]
 #[cfg_attr(feature = "dev-tools", derive(EnumIter))]
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Model {
     Claude(ClaudeVersion),
     ChatGpt(ChatGptVersion),
     Gemini(GeminiVersion),
+    /// Any server speaking openai's `/chat/completions` dialect (vLLM, llama.cpp server, LM Studio, Groq, Together, ...)
+    /// `base_url` is the api root, e.g. "http://gpu-box:8000/v1"; auth is optional (see environment.rs)
+    OpenAiCompatible {
+        base_url: String,
+        model_id: String,
+    },
+    /// An Azure OpenAI deployment, by deployment name; the resource endpoint comes from the env or `with_base_url`
+    AzureOpenAi {
+        deployment: String,
+    },
+    /// A model served by ollama's native api, by the name it was pulled under (e.g. "llama3.1:8b")
+    Ollama(String),
+    /// A model behind a provider registered with `LlmClient::with_provider`, looked up by the provider's name
+    Custom {
+        provider: String,
+        model_id: String,
+    },
     #[cfg(feature = "dev-tools")]
     None,
 }
 
 #[cfg_attr(feature = "dev-tools", derive(EnumIter))]
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ClaudeVersion {
     Sonnet4,
+    /// Any model id the api accepts, e.g. a release newer than this crate
+    Custom(String),
     None,
 }
 
 #[cfg_attr(feature = "dev-tools", derive(EnumIter))]
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ChatGptVersion {
     Gpt5,
+    /// Any model id the api accepts, e.g. a release newer than this crate
+    Custom(String),
     None,
 }
 
 #[cfg_attr(feature = "dev-tools", derive(EnumIter))]
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum GeminiVersion {
+    Gemini25Pro,
+    Gemini25Flash,
+    Gemini25FlashLite,
+    /// Any model id the api accepts, e.g. a release newer than this crate
+    Custom(String),
     None,
 }
 
@@ -72,30 +103,199 @@ impl Default for GeminiVersion {
 }
 
 impl Model {
-    pub fn to_model_string(&self) -> Option<&'static str> {
+    pub fn to_model_string(&self) -> Option<&str> {
         match self {
             Model::Claude(ver) => match ver {
                 ClaudeVersion::Sonnet4 => Some("claude-sonnet-4-20250514"),
+                ClaudeVersion::Custom(id) => Some(id),
                 ClaudeVersion::None => None,
             },
             Model::ChatGpt(ver) => match ver {
                 ChatGptVersion::Gpt5 => Some("gpt-5"),
+                ChatGptVersion::Custom(id) => Some(id),
                 ChatGptVersion::None => None,
             },
             Model::Gemini(ver) => match ver {
+                GeminiVersion::Gemini25Pro => Some("gemini-2.5-pro"),
+                GeminiVersion::Gemini25Flash => Some("gemini-2.5-flash"),
+                GeminiVersion::Gemini25FlashLite => Some("gemini-2.5-flash-lite"),
+                GeminiVersion::Custom(id) => Some(id),
                 GeminiVersion::None => None,
             },
+            Model::OpenAiCompatible { model_id, .. } => Some(model_id),
+            // azure routes by deployment in the url, the body's model is ignored
+            Model::AzureOpenAi { deployment } => Some(deployment),
+            Model::Ollama(name) => Some(name),
+            Model::Custom { model_id, .. } => Some(model_id),
             #[cfg(feature = "dev-tools")]
             Model::None => panic!("dev-tools only"),
         }
     }
 
-    // TODO-4: add "mode" so we can spec within-model
-    pub(crate) fn to_target_url(&self) -> &'static str {
+    /// A provider picked without a model, e.g. `"gemini".parse()`; there's nothing to send requests to
+    pub fn is_versionless(&self) -> bool {
+        matches!(
+            self,
+            Model::Claude(ClaudeVersion::None)
+                | Model::ChatGpt(ChatGptVersion::None)
+                | Model::Gemini(GeminiVersion::None)
+        )
+    }
+
+    /// What the model accepts & its limits; models we don't know (custom ids, self-hosted, registered providers)
+    /// get no limits & every feature, leaving the server to reject what it can't do
+    pub fn capabilities(&self) -> ModelCapabilities {
         match self {
-            Model::Claude(_) => "https://api.anthropic.com/v1/messages",
-            Model::ChatGpt(_) => "https://api.openai.com/v1/chat/completions",
-            _ => todo!("gem"),
+            Model::Claude(ver) => match ver {
+                ClaudeVersion::Sonnet4 => ModelCapabilities {
+                    context_window: Some(200_000),
+                    max_output_tokens: Some(64_000),
+                    ..ModelCapabilities::anthropic()
+                },
+                ClaudeVersion::Custom(_) => ModelCapabilities::anthropic(),
+                ClaudeVersion::None => ModelCapabilities::none(),
+            },
+            Model::ChatGpt(ver) => match ver {
+                // reasoning models only run at their default sampling
+                ChatGptVersion::Gpt5 => ModelCapabilities {
+                    context_window: Some(400_000),
+                    max_output_tokens: Some(128_000),
+                    temperature: false,
+                    top_p: false,
+                    stop_sequences: false,
+                    penalties: false,
+                    logit_bias: false,
+                    ..ModelCapabilities::openai()
+                },
+                ChatGptVersion::Custom(_) => ModelCapabilities::openai(),
+                ChatGptVersion::None => ModelCapabilities::none(),
+            },
+            Model::Gemini(ver) => match ver {
+                GeminiVersion::Gemini25Pro
+                | GeminiVersion::Gemini25Flash
+                | GeminiVersion::Gemini25FlashLite => ModelCapabilities {
+                    context_window: Some(1_048_576),
+                    max_output_tokens: Some(65_536),
+                    ..ModelCapabilities::google()
+                },
+                GeminiVersion::Custom(_) => ModelCapabilities::google(),
+                GeminiVersion::None => ModelCapabilities::none(),
+            },
+            // whatever is deployed, the api is still openai's
+            Model::AzureOpenAi { .. } => ModelCapabilities::openai(),
+            Model::Ollama(_) => ModelCapabilities {
+                logit_bias: false,
+                ..ModelCapabilities::unknown()
+            },
+            Model::OpenAiCompatible { .. } | Model::Custom { .. } => ModelCapabilities::unknown(),
+            #[cfg(feature = "dev-tools")]
+            Model::None => panic!("dev-tools only"),
+        }
+    }
+
+    /// Provider api root that endpoints are joined onto, overridable per config or via env
+    pub fn default_base_url(&self) -> &str {
+        match self {
+            Model::Claude(_) => "https://api.anthropic.com/v1",
+            Model::ChatGpt(_) => "https://api.openai.com/v1",
+            Model::Gemini(_) => "https://generativelanguage.googleapis.com/v1beta",
+            Model::OpenAiCompatible { base_url, .. } => base_url,
+            // endpoints are per azure resource, there is no sensible default (see ModelConfigBuilder::build)
+            Model::AzureOpenAi { .. } => "",
+            Model::Ollama(_) => "http://localhost:11434/api",
+            // only set if the config asks for one, a provider may not need it at all
+            Model::Custom { .. } => "",
+            #[cfg(feature = "dev-tools")]
+            Model::None => panic!("dev-tools only"),
+        }
+    }
+
+    pub(crate) fn to_target_url(&self, base_url: &str) -> String {
+        match self {
+            Model::Claude(_) => format!("{base_url}/messages"),
+            Model::ChatGpt(_) | Model::OpenAiCompatible { .. } => {
+                format!("{base_url}/chat/completions")
+            }
+            Model::AzureOpenAi { deployment } => format!(
+                "{base_url}/openai/deployments/{deployment}/chat/completions?api-version={}",
+                self.to_api_version()
+            ),
+            Model::Ollama(_) => format!("{base_url}/chat"),
+            // gemini scopes the endpoint by model rather than taking it in the body,
+            // versionless models are refused before a request gets this far (see Model::is_versionless)
+            Model::Gemini(_) => format!(
+                "{base_url}/models/{}:generateContent",
+                self.to_model_string().unwrap_or_default()
+            ),
+            // a custom provider names its own endpoint, the config's base url is all there is to go on
+            Model::Custom { .. } => base_url.to_string(),
+            #[cfg(feature = "dev-tools")]
+            Model::None => panic!("dev-tools only"),
+        }
+    }
+
+    pub(crate) fn to_stream_target_url(&self, base_url: &str) -> String {
+        match self {
+            // claude & chatgpt stream from the same endpoint when the body asks for it
+            Model::Claude(_)
+            | Model::ChatGpt(_)
+            | Model::OpenAiCompatible { .. }
+            | Model::AzureOpenAi { .. }
+            | Model::Ollama(_)
+            | Model::Custom { .. } => self.to_target_url(base_url),
+            Model::Gemini(_) => format!(
+                "{base_url}/models/{}:streamGenerateContent?alt=sse",
+                self.to_model_string().unwrap_or_default()
+            ),
+            #[cfg(feature = "dev-tools")]
+            Model::None => panic!("dev-tools only"),
+        }
+    }
+
+    /// None for providers aipi doesn't embed with
+    pub(crate) fn to_embeddings_url(&self, base_url: &str) -> Option<String> {
+        match self {
+            Model::ChatGpt(_) | Model::OpenAiCompatible { .. } => {
+                Some(format!("{base_url}/embeddings"))
+            }
+            // always the batch endpoint, a single input is a batch of one
+            Model::Gemini(_) => Some(format!(
+                "{base_url}/models/{}:batchEmbedContents",
+                self.to_model_string().unwrap_or_default()
+            )),
+            Model::Claude(_)
+            | Model::AzureOpenAi { .. }
+            | Model::Ollama(_)
+            | Model::Custom { .. } => None,
+            #[cfg(feature = "dev-tools")]
+            Model::None => panic!("dev-tools only"),
+        }
+    }
+
+    #[cfg(feature = "dev-tools")]
+    pub(crate) fn to_models_url(&self, base_url: &str) -> String {
+        match self {
+            Model::Ollama(_) => format!("{base_url}/tags"),
+            Model::AzureOpenAi { .. } => {
+                format!(
+                    "{base_url}/openai/models?api-version={}",
+                    self.to_api_version()
+                )
+            }
+            _ => format!("{base_url}/models"),
+        }
+    }
+
+    /// Response header carrying the provider's id for a request, useful when reporting issues upstream
+    pub(crate) fn to_request_id_header(&self) -> Option<&'static str> {
+        match self {
+            Model::Claude(_) => Some("request-id"),
+            Model::ChatGpt(_) | Model::OpenAiCompatible { .. } | Model::AzureOpenAi { .. } => {
+                Some("x-request-id")
+            }
+            Model::Gemini(_) | Model::Ollama(_) | Model::Custom { .. } => None,
+            #[cfg(feature = "dev-tools")]
+            Model::None => panic!("dev-tools only"),
         }
     }
 
@@ -103,16 +303,238 @@ impl Model {
         match self {
             Model::Claude(_) => "2023-06-01",
             Model::ChatGpt(_) => panic!("internal unreachable, not relevant param for chatgpt"),
-            _ => todo!("gem"),
+            Model::Gemini(_) => panic!("internal unreachable, not relevant param for gemini"),
+            Model::OpenAiCompatible { .. } => {
+                panic!("internal unreachable, not relevant param for openai compatible servers")
+            }
+            // sent as the api-version query param
+            Model::AzureOpenAi { .. } => "2024-10-21",
+            Model::Ollama(_) => panic!("internal unreachable, not relevant param for ollama"),
+            Model::Custom { .. } => {
+                panic!("internal unreachable, not relevant param for custom models")
+            }
+            #[cfg(feature = "dev-tools")]
+            Model::None => panic!("dev-tools only"),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Limits are in tokens, None when unknown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelCapabilities {
+    pub context_window: Option<usize>,
+    pub max_output_tokens: Option<usize>,
+    pub vision: bool,
+    pub tools: bool,
+    pub streaming: bool,
+    pub system_prompt: bool,
+    /// false when only the provider's default of 1 is accepted
+    pub temperature: bool,
+    /// upper bound of the accepted temperature range (the lower is always 0), None when unbounded or unknown
+    pub max_temperature: Option<f64>,
+    pub top_p: bool,
+    pub top_k: bool,
+    pub stop_sequences: bool,
+    pub seed: bool,
+    /// presence & frequency penalty
+    pub penalties: bool,
+    pub logit_bias: bool,
+    /// returns several candidates from one request (openai's n, gemini's candidateCount), otherwise LlmClient fans out
+    pub candidates: bool,
+    /// thinks before answering (extended thinking, reasoning effort, thinking budget)
+    pub reasoning: bool,
+}
+
+impl ModelCapabilities {
+    fn all() -> Self {
+        ModelCapabilities {
+            context_window: None,
+            max_output_tokens: None,
+            vision: true,
+            tools: true,
+            streaming: true,
+            system_prompt: true,
+            temperature: true,
+            max_temperature: None,
+            top_p: true,
+            top_k: true,
+            stop_sequences: true,
+            seed: true,
+            penalties: true,
+            logit_bias: true,
+            candidates: true,
+            reasoning: true,
+        }
+    }
+
+    // what each api takes regardless of model, known models narrow it further
+    fn anthropic() -> Self {
+        ModelCapabilities {
+            max_temperature: Some(1.0),
+            seed: false,
+            penalties: false,
+            logit_bias: false,
+            candidates: false,
+            ..Self::all()
+        }
+    }
+
+    fn openai() -> Self {
+        ModelCapabilities {
+            max_temperature: Some(2.0),
+            top_k: false,
+            ..Self::all()
+        }
+    }
+
+    fn google() -> Self {
+        ModelCapabilities {
+            max_temperature: Some(2.0),
+            logit_bias: false,
+            ..Self::all()
+        }
+    }
+
+    // asking an unknown server for several candidates at once risks silently getting one back
+    fn unknown() -> Self {
+        ModelCapabilities {
+            candidates: false,
+            ..Self::all()
+        }
+    }
+
+    // placeholder versions can't be sent anything
+    fn none() -> Self {
+        ModelCapabilities {
+            vision: false,
+            tools: false,
+            streaming: false,
+            system_prompt: false,
+            temperature: false,
+            top_p: false,
+            top_k: false,
+            stop_sequences: false,
+            seed: false,
+            penalties: false,
+            logit_bias: false,
+            candidates: false,
+            reasoning: false,
+            ..Self::all()
+        }
+    }
+}
+
+impl ClaudeVersion {
+    fn from_model_id(id: &str) -> Self {
+        match id {
+            "" => ClaudeVersion::None,
+            "claude-sonnet-4-20250514" => ClaudeVersion::Sonnet4,
+            _ => ClaudeVersion::Custom(id.to_string()),
+        }
+    }
+}
+
+impl ChatGptVersion {
+    fn from_model_id(id: &str) -> Self {
+        match id {
+            "" => ChatGptVersion::None,
+            "gpt-5" => ChatGptVersion::Gpt5,
+            _ => ChatGptVersion::Custom(id.to_string()),
+        }
+    }
+}
+
+impl GeminiVersion {
+    fn from_model_id(id: &str) -> Self {
+        match id {
+            "" => GeminiVersion::None,
+            "gemini-2.5-pro" => GeminiVersion::Gemini25Pro,
+            "gemini-2.5-flash" => GeminiVersion::Gemini25Flash,
+            "gemini-2.5-flash-lite" => GeminiVersion::Gemini25FlashLite,
+            _ => GeminiVersion::Custom(id.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ModelParseError {
+    UnknownProvider(String),
+    /// provider recognised but the rest doesn't fit its shape, e.g. "ollama:" or "openai-compatible:gpt-oss"
+    Malformed(String),
+}
+
+impl Display for ModelParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{self:?}"))
+    }
+}
+
+impl Error for ModelParseError {}
+
+/// "provider:model-id", e.g. "claude:claude-opus-4-1", "openai:gpt-4.1-mini", "gemini:gemini-2.5-pro", "ollama:llama3.1:8b",
+/// "azure:my-deployment", "openai-compatible:model-id@http://gpu-box:8000/v1" or "custom:provider-name:model-id".
+/// Ids we have a typed variant for parse to it, anything else becomes that provider's `Custom` version.
+impl FromStr for Model {
+    type Err = ModelParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (provider, id) = s.split_once(':').unwrap_or((s, ""));
+        let malformed = || ModelParseError::Malformed(s.to_string());
+        match provider.to_ascii_lowercase().as_str() {
+            "claude" | "anthropic" => Ok(Model::Claude(ClaudeVersion::from_model_id(id))),
+            "openai" | "chatgpt" => Ok(Model::ChatGpt(ChatGptVersion::from_model_id(id))),
+            "gemini" | "google" => Ok(Model::Gemini(GeminiVersion::from_model_id(id))),
+            "openai-compatible" => match id.split_once('@') {
+                Some((model_id, base_url)) if !model_id.is_empty() && !base_url.is_empty() => {
+                    Ok(Model::OpenAiCompatible {
+                        base_url: base_url.to_string(),
+                        model_id: model_id.to_string(),
+                    })
+                }
+                _ => Err(malformed()),
+            },
+            "azure" if !id.is_empty() => Ok(Model::AzureOpenAi {
+                deployment: id.to_string(),
+            }),
+            "ollama" if !id.is_empty() => Ok(Model::Ollama(id.to_string())),
+            "custom" => match id.split_once(':') {
+                Some((provider, model_id)) if !provider.is_empty() => Ok(Model::Custom {
+                    provider: provider.to_string(),
+                    model_id: model_id.to_string(),
+                }),
+                _ => Err(malformed()),
+            },
+            "azure" | "ollama" => Err(malformed()),
+            _ => Err(ModelParseError::UnknownProvider(provider.to_string())),
+        }
+    }
+}
+
+/// The `FromStr` form, so a displayed model parses back to itself
+impl Display for Model {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Model::Claude(_) => write!(f, "claude:{}", self.to_model_string().unwrap_or_default()),
+            Model::ChatGpt(_) => write!(f, "openai:{}", self.to_model_string().unwrap_or_default()),
+            Model::Gemini(_) => write!(f, "gemini:{}", self.to_model_string().unwrap_or_default()),
+            Model::OpenAiCompatible { base_url, model_id } => {
+                write!(f, "openai-compatible:{model_id}@{base_url}")
+            }
+            Model::AzureOpenAi { deployment } => write!(f, "azure:{deployment}"),
+            Model::Ollama(name) => write!(f, "ollama:{name}"),
+            Model::Custom { provider, model_id } => write!(f, "custom:{provider}:{model_id}"),
+            #[cfg(feature = "dev-tools")]
+            Model::None => write!(f, "none"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Role {
     User,
     Ai,
     System,
+    Tool,
 }
 
 impl Role {
@@ -122,27 +544,97 @@ impl Role {
                 Role::User => "user".to_string(),
                 Role::Ai => "assistant".to_string(),
                 Role::System => "system".to_string(), // TODO-2: this might not be correct in Claude spec and this function may need to return a result instead
+                Role::Tool => "user".to_string(), // claude returns tool results as user content blocks
             },
             Model::ChatGpt(ver) => match ver {
-                ChatGptVersion::Gpt5 | ChatGptVersion::None => match self {
+                ChatGptVersion::Gpt5 | ChatGptVersion::Custom(_) | ChatGptVersion::None => {
+                    match self {
+                        Role::User => "user".to_string(),
+                        Role::Ai => "assistant".to_string(),
+                        Role::System => "developer".to_string(),
+                        Role::Tool => "tool".to_string(),
+                    }
+                }
+            },
+            Model::Gemini(_) => match self {
+                Role::User => "user".to_string(),
+                Role::Ai => "model".to_string(),
+                Role::System => "system".to_string(), // gemini takes system via systemInstruction, not contents
+                Role::Tool => "user".to_string(), // gemini returns function responses as user parts
+            },
+            // "developer" is openai-only, compatible servers (& azure's GA api version) still expect "system"
+            Model::OpenAiCompatible { .. } | Model::AzureOpenAi { .. } | Model::Ollama(_) => {
+                match self {
                     Role::User => "user".to_string(),
                     Role::Ai => "assistant".to_string(),
-                    Role::System => "developer".to_string(),
-                },
+                    Role::System => "system".to_string(),
+                    Role::Tool => "tool".to_string(),
+                }
+            }
+            // the common names, for providers that want them
+            Model::Custom { .. } => match self {
+                Role::User => "user".to_string(),
+                Role::Ai => "assistant".to_string(),
+                Role::System => "system".to_string(),
+                Role::Tool => "tool".to_string(),
             },
-            _ => todo!("gem"),
+            #[cfg(feature = "dev-tools")]
+            Model::None => panic!("dev-tools only"),
         }
     }
 }
 
 // TODO-5: Consider pub-ing fields at crate level
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelConfig {
     pub model: Model,
+    // never persisted, re-resolved from the environment when a config is loaded
+    #[serde(skip, default = "empty_token")]
     pub token: secrecy::SecretString,
     pub system_prompt: Option<String>,
     pub max_tokens: usize,
-    pub temperature: f64,
+    // None leaves it to the provider's default, always None for models that don't take one
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    // sampling parameters below are only sent when set
+    #[serde(default)]
+    pub top_p: Option<f64>,
+    #[serde(default)]
+    pub top_k: Option<u32>,
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
+    #[serde(default)]
+    pub seed: Option<i64>,
+    #[serde(default)]
+    pub presence_penalty: Option<f64>,
+    #[serde(default)]
+    pub frequency_penalty: Option<f64>,
+    /// token id -> bias in [-100, 100]
+    #[serde(default)]
+    pub logit_bias: BTreeMap<u32, f64>,
+    // per request rather than per config, set by LlmClient::send_candidates
+    #[serde(skip)]
+    pub(crate) candidates: Option<usize>,
+    pub tools: Vec<ToolDefinition>,
+    // explicit override only, env & provider defaults are resolved per request (see ModelConfig::base_url)
+    #[serde(default)]
+    pub base_url: Option<String>,
+}
+
+impl ModelConfig {
+    /// Api root requests are sent to: the configured base url, else the env override, else the provider default
+    pub fn base_url(&self) -> String {
+        let base_url = self
+            .base_url
+            .clone()
+            .or_else(|| get_base_url(&self.model))
+            .unwrap_or_else(|| self.model.default_base_url().to_string());
+        base_url.trim_end_matches('/').to_string()
+    }
+}
+
+fn empty_token() -> SecretString {
+    SecretString::from("")
 }
 
 #[allow(dead_code)]
@@ -166,6 +658,15 @@ pub struct ModelConfigBuilder {
     system_prompt: Option<String>,
     max_tokens: Option<usize>,
     temperature: Option<f64>,
+    top_p: Option<f64>,
+    top_k: Option<u32>,
+    stop_sequences: Vec<String>,
+    seed: Option<i64>,
+    presence_penalty: Option<f64>,
+    frequency_penalty: Option<f64>,
+    logit_bias: BTreeMap<u32, f64>,
+    tools: Vec<ToolDefinition>,
+    base_url: Option<String>,
     errors: Vec<ModelConfigBuildError>,
 }
 
@@ -176,6 +677,15 @@ impl ModelConfigBuilder {
             system_prompt: None,
             max_tokens: None,
             temperature: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: Vec::new(),
+            seed: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            logit_bias: BTreeMap::new(),
+            tools: Vec::new(),
+            base_url: None,
             errors: Vec::new(),
         }
     }
@@ -190,16 +700,179 @@ impl ModelConfigBuilder {
         self
     }
 
+    // bounds depend on the model, checked in build
     pub fn with_temperature(mut self, temperature: f64) -> Self {
-        if !(0.0..=1.0).contains(&temperature) {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_top_p(mut self, top_p: f64) -> Self {
+        if !(0.0..=1.0).contains(&top_p) {
             self.errors.push(ModelConfigBuildError::Validation(format!(
-                "Temperature parameter is out of bounds. Value supplied: {temperature}; Temperature bounds: [0, 1]."
+                "Top p parameter is out of bounds. Value supplied: {top_p}; Top p bounds: [0, 1]."
             )));
         }
-        self.temperature = Some(temperature);
+        self.top_p = Some(top_p);
+        self
+    }
+
+    pub fn with_top_k(mut self, top_k: u32) -> Self {
+        if top_k == 0 {
+            self.errors.push(ModelConfigBuildError::Validation(
+                "Top k parameter must be at least 1.".to_string(),
+            ));
+        }
+        self.top_k = Some(top_k);
+        self
+    }
+
+    /// Generation stops before any of these would be output, can be called repeatedly
+    pub fn with_stop_sequence(mut self, stop_sequence: String) -> Self {
+        self.stop_sequences.push(stop_sequence);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: i64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_presence_penalty(mut self, presence_penalty: f64) -> Self {
+        if !(-2.0..=2.0).contains(&presence_penalty) {
+            self.errors.push(ModelConfigBuildError::Validation(format!(
+                "Presence penalty parameter is out of bounds. Value supplied: {presence_penalty}; Presence penalty bounds: [-2, 2]."
+            )));
+        }
+        self.presence_penalty = Some(presence_penalty);
         self
     }
 
+    pub fn with_frequency_penalty(mut self, frequency_penalty: f64) -> Self {
+        if !(-2.0..=2.0).contains(&frequency_penalty) {
+            self.errors.push(ModelConfigBuildError::Validation(format!(
+                "Frequency penalty parameter is out of bounds. Value supplied: {frequency_penalty}; Frequency penalty bounds: [-2, 2]."
+            )));
+        }
+        self.frequency_penalty = Some(frequency_penalty);
+        self
+    }
+
+    /// Bias `token` (a tokenizer id) up or down, can be called repeatedly
+    pub fn with_logit_bias(mut self, token: u32, bias: f64) -> Self {
+        if !(-100.0..=100.0).contains(&bias) {
+            self.errors.push(ModelConfigBuildError::Validation(format!(
+                "Logit bias is out of bounds. Value supplied: {bias} (token {token}); Logit bias bounds: [-100, 100]."
+            )));
+        }
+        self.logit_bias.insert(token, bias);
+        self
+    }
+
+    pub fn with_tool(mut self, tool: ToolDefinition) -> Self {
+        self.tools.push(tool);
+        self
+    }
+
+    pub fn with_tools(mut self, tools: Vec<ToolDefinition>) -> Self {
+        self.tools.extend(tools);
+        self
+    }
+
+    /// Send requests to this api root instead of the provider's, e.g. a proxy or a local stand-in server
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        if !(base_url.starts_with("http://") || base_url.starts_with("https://")) {
+            self.errors.push(ModelConfigBuildError::Validation(format!(
+                "Base url must be http(s). Value supplied: {base_url}."
+            )));
+        }
+        self.base_url = Some(base_url);
+        self
+    }
+
+    // reject what the model is known not to take, unknown models pass everything through
+    fn check_capabilities(&mut self) {
+        let capabilities = self.model.capabilities();
+        let model = &self.model;
+        if model.is_versionless() {
+            self.errors.push(ModelConfigBuildError::Validation(format!(
+                "{model} needs a model version to send requests to."
+            )));
+        }
+        if let (Some(max_tokens), Some(limit)) = (self.max_tokens, capabilities.max_output_tokens)
+            && max_tokens > limit
+        {
+            self.errors.push(ModelConfigBuildError::Validation(format!(
+                "Max tokens parameter exceeds the model's output limit. Value supplied: {max_tokens}; {model} limit: {limit}."
+            )));
+        }
+        if !self.tools.is_empty() && !capabilities.tools {
+            self.errors.push(ModelConfigBuildError::Validation(format!(
+                "{model} does not support tools."
+            )));
+        }
+        if self.system_prompt.is_some() && !capabilities.system_prompt {
+            self.errors.push(ModelConfigBuildError::Validation(format!(
+                "{model} does not support a system prompt."
+            )));
+        }
+        match self.temperature {
+            // asking for the default explicitly is harmless
+            Some(temperature) if !capabilities.temperature && temperature != 1.0 => {
+                self.errors.push(ModelConfigBuildError::Validation(format!(
+                    "{model} only supports the default temperature of 1. Value supplied: {temperature}."
+                )));
+            }
+            Some(temperature) => {
+                let max = capabilities.max_temperature.unwrap_or(f64::INFINITY);
+                // also catches NaN
+                if !(0.0..=max).contains(&temperature) {
+                    let bounds = match capabilities.max_temperature {
+                        Some(max) => format!("[0, {max}]"),
+                        None => "[0, ∞)".to_string(),
+                    };
+                    self.errors.push(ModelConfigBuildError::Validation(format!(
+                        "Temperature parameter is out of bounds. Value supplied: {temperature}; {model} bounds: {bounds}."
+                    )));
+                }
+            }
+            None => (),
+        }
+
+        let sampling = [
+            ("top_p", self.top_p.is_some(), capabilities.top_p),
+            ("top_k", self.top_k.is_some(), capabilities.top_k),
+            (
+                "stop sequences",
+                !self.stop_sequences.is_empty(),
+                capabilities.stop_sequences,
+            ),
+            ("seed", self.seed.is_some(), capabilities.seed),
+            (
+                "presence penalty",
+                self.presence_penalty.is_some(),
+                capabilities.penalties,
+            ),
+            (
+                "frequency penalty",
+                self.frequency_penalty.is_some(),
+                capabilities.penalties,
+            ),
+            (
+                "logit bias",
+                !self.logit_bias.is_empty(),
+                capabilities.logit_bias,
+            ),
+        ];
+        for (parameter, _, _) in sampling
+            .iter()
+            .filter(|(_, set, supported)| *set && !supported)
+        {
+            self.errors.push(ModelConfigBuildError::Validation(format!(
+                "{model} does not support {parameter}."
+            )));
+        }
+    }
+
     pub fn build(mut self) -> Result<ModelConfig, ModelConfigBuildError> {
         let token = match get_api_key(&self.model) {
             Ok(t) => t,
@@ -208,6 +881,16 @@ impl ModelConfigBuilder {
                 SecretString::from("")
             }
         };
+        if let Model::AzureOpenAi { .. } = self.model
+            && self.base_url.is_none()
+            && get_base_url(&self.model).is_none()
+        {
+            self.errors.push(ModelConfigBuildError::Validation(format!(
+                "Azure OpenAI needs the resource endpoint, set {BASE_URL_AZURE_OPENAI} in your env or use with_base_url."
+            )));
+        }
+
+        self.check_capabilities();
 
         match self.errors.len() {
             0 => Ok(ModelConfig {
@@ -215,10 +898,252 @@ impl ModelConfigBuilder {
                 token,
                 system_prompt: self.system_prompt,
                 max_tokens: self.max_tokens.unwrap_or(1024),
-                temperature: self.temperature.unwrap_or(0.5),
+                temperature: self.temperature,
+                top_p: self.top_p,
+                top_k: self.top_k,
+                stop_sequences: self.stop_sequences,
+                seed: self.seed,
+                presence_penalty: self.presence_penalty,
+                frequency_penalty: self.frequency_penalty,
+                logit_bias: self.logit_bias,
+                candidates: None,
+                tools: self.tools,
+                base_url: self.base_url,
             }),
             1 => Err(self.errors.pop().unwrap()),
             _ => Err(ModelConfigBuildError::Multi(self.errors)),
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A config for `model` without the env lookup build does for its api key
+    pub(crate) fn config(model: Model) -> ModelConfig {
+        serde_json::from_value(serde_json::json!({
+            "model": model,
+            "max_tokens": 1024,
+            "tools": [],
+        }))
+        .expect("deserializable config")
+    }
+
+    fn ollama() -> ModelConfigBuilder {
+        ModelConfigBuilder::new(Model::Ollama("llama3.2".to_string()))
+    }
+
+    // messages of the validation errors build reports, a missing api key aside
+    fn validation_errors(builder: ModelConfigBuilder) -> Vec<String> {
+        let errors = match builder.build() {
+            Ok(_) => return Vec::new(),
+            Err(ModelConfigBuildError::Multi(errors)) => errors,
+            Err(e) => vec![e],
+        };
+        errors
+            .into_iter()
+            .filter_map(|e| match e {
+                ModelConfigBuildError::Validation(message) => Some(message),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn displayed_models_parse_back() {
+        let models = [
+            Model::Claude(ClaudeVersion::Sonnet4),
+            Model::Claude(ClaudeVersion::Custom("claude-opus-4-1".to_string())),
+            Model::Claude(ClaudeVersion::None),
+            Model::ChatGpt(ChatGptVersion::Gpt5),
+            Model::ChatGpt(ChatGptVersion::Custom("gpt-4.1-mini".to_string())),
+            Model::Gemini(GeminiVersion::Gemini25Pro),
+            Model::Gemini(GeminiVersion::Gemini25Flash),
+            Model::Gemini(GeminiVersion::Gemini25FlashLite),
+            Model::Gemini(GeminiVersion::Custom("gemini-3-pro".to_string())),
+            Model::OpenAiCompatible {
+                base_url: "http://gpu-box:8000/v1".to_string(),
+                model_id: "qwen3".to_string(),
+            },
+            Model::AzureOpenAi {
+                deployment: "my-deployment".to_string(),
+            },
+            Model::Ollama("llama3.1:8b".to_string()),
+            Model::Custom {
+                provider: "acme".to_string(),
+                model_id: "rocket:1".to_string(),
+            },
+        ];
+        for model in models {
+            assert_eq!(
+                model.to_string().parse::<Model>().unwrap(),
+                model,
+                "{model}"
+            );
+        }
+    }
+
+    #[test]
+    fn model_strings_parse_to_typed_versions() {
+        let parse = |s: &str| s.parse::<Model>().unwrap();
+        assert_eq!(
+            parse("anthropic:claude-sonnet-4-20250514"),
+            Model::Claude(ClaudeVersion::Sonnet4)
+        );
+        assert_eq!(parse("ChatGPT:gpt-5"), Model::ChatGpt(ChatGptVersion::Gpt5));
+        assert_eq!(
+            parse("google:gemini-2.5-flash"),
+            Model::Gemini(GeminiVersion::Gemini25Flash)
+        );
+        assert_eq!(parse("gemini"), Model::Gemini(GeminiVersion::None));
+        assert_eq!(
+            parse("custom:acme:"),
+            Model::Custom {
+                provider: "acme".to_string(),
+                model_id: String::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn malformed_model_strings_are_rejected() {
+        for s in [
+            "ollama",
+            "ollama:",
+            "azure:",
+            "openai-compatible:qwen3",
+            "openai-compatible:@http://gpu-box:8000/v1",
+            "openai-compatible:qwen3@",
+            "custom:rocket-1",
+            "custom::rocket-1",
+        ] {
+            assert!(
+                matches!(s.parse::<Model>(), Err(ModelParseError::Malformed(_))),
+                "{s}"
+            );
+        }
+        assert!(matches!(
+            "mistral:large".parse::<Model>(),
+            Err(ModelParseError::UnknownProvider(p)) if p == "mistral"
+        ));
+        assert!(matches!(
+            "".parse::<Model>(),
+            Err(ModelParseError::UnknownProvider(_))
+        ));
+    }
+
+    #[test]
+    fn temperature_is_only_set_when_asked_for() {
+        assert_eq!(ollama().build().unwrap().temperature, None);
+        assert_eq!(
+            ollama().with_temperature(0.7).build().unwrap().temperature,
+            Some(0.7)
+        );
+    }
+
+    #[test]
+    fn temperature_out_of_bounds_is_rejected() {
+        assert!(ollama().with_temperature(-0.1).build().is_err());
+        assert!(ollama().with_temperature(f64::NAN).build().is_err());
+    }
+
+    #[test]
+    fn versionless_models_are_rejected() {
+        for model in ["claude", "openai", "gemini"] {
+            let model = model.parse::<Model>().unwrap();
+            assert!(model.is_versionless());
+            let errors = validation_errors(ModelConfigBuilder::new(model));
+            assert!(errors.iter().any(|m| m.contains("needs a model version")));
+        }
+    }
+
+    #[test]
+    fn azure_routes_by_deployment_with_an_api_version() {
+        let model = Model::AzureOpenAi {
+            deployment: "gpt-4o-prod".to_string(),
+        };
+        let base_url = "https://my-resource.openai.azure.com";
+        assert_eq!(
+            model.to_target_url(base_url),
+            "https://my-resource.openai.azure.com/openai/deployments/gpt-4o-prod/chat/completions?api-version=2024-10-21"
+        );
+        assert_eq!(
+            model.to_stream_target_url(base_url),
+            model.to_target_url(base_url)
+        );
+        let compatible = Model::OpenAiCompatible {
+            model_id: "qwen3".to_string(),
+            base_url: "http://localhost:8000/v1".to_string(),
+        };
+        assert_eq!(
+            compatible.to_target_url(compatible.default_base_url()),
+            "http://localhost:8000/v1/chat/completions"
+        );
+    }
+
+    #[test]
+    fn max_tokens_above_the_models_output_limit_is_rejected() {
+        let sonnet = || ModelConfigBuilder::new(Model::Claude(ClaudeVersion::Sonnet4));
+        let limit = Model::Claude(ClaudeVersion::Sonnet4)
+            .capabilities()
+            .max_output_tokens
+            .unwrap();
+        assert!(validation_errors(sonnet().with_max_tokens(limit)).is_empty());
+        assert_eq!(
+            validation_errors(sonnet().with_max_tokens(limit + 1)),
+            [format!(
+                "Max tokens parameter exceeds the model's output limit. Value supplied: {}; claude:claude-sonnet-4-20250514 limit: {limit}.",
+                limit + 1
+            )]
+        );
+        // no limit is known for local models, the server decides
+        assert!(validation_errors(ollama().with_max_tokens(1_000_000)).is_empty());
+    }
+
+    #[test]
+    fn reasoning_models_only_take_the_default_temperature() {
+        let gpt5 = || ModelConfigBuilder::new(Model::ChatGpt(ChatGptVersion::Gpt5));
+        assert!(validation_errors(gpt5().with_temperature(1.0)).is_empty());
+        let errors = validation_errors(gpt5().with_temperature(0.2));
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].contains("only supports the default temperature"),
+            "{}",
+            errors[0]
+        );
+    }
+
+    #[test]
+    fn temperature_bounds_follow_the_provider() {
+        let errors = |model: Model, temperature: f64| {
+            validation_errors(ModelConfigBuilder::new(model).with_temperature(temperature)).len()
+        };
+        let sonnet = Model::Claude(ClaudeVersion::Sonnet4);
+        let gpt = Model::ChatGpt(ChatGptVersion::Custom("gpt-4.1".to_string()));
+        let flash = Model::Gemini(GeminiVersion::Gemini25Flash);
+        assert_eq!(errors(sonnet.clone(), 1.0), 0);
+        assert_eq!(errors(sonnet, 1.5), 1);
+        assert_eq!(errors(gpt.clone(), 2.0), 0);
+        assert_eq!(errors(gpt, 2.1), 1);
+        assert_eq!(errors(flash, 2.0), 0);
+    }
+
+    #[test]
+    fn each_unsupported_parameter_is_reported() {
+        let builder = ModelConfigBuilder::new(Model::Claude(ClaudeVersion::Sonnet4))
+            .with_seed(7)
+            .with_presence_penalty(0.5)
+            .with_logit_bias(42, -100.0)
+            .with_top_k(40);
+        let model = "claude:claude-sonnet-4-20250514";
+        assert_eq!(
+            validation_errors(builder),
+            [
+                format!("{model} does not support seed."),
+                format!("{model} does not support presence penalty."),
+                format!("{model} does not support logit bias."),
+            ]
+        );
+    }
+}
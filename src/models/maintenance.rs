@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use secrecy::ExposeSecret;
 use serde::Deserialize;
 use strum::IntoEnumIterator;
-use tracing::error;
+use tracing::{error, info};
 
 use crate::{client::WithModelHeaders, environment::get_api_key, models::ModelConfigBuilder};
 
@@ -12,7 +12,7 @@ use super::{ChatGptVersion, ClaudeVersion, GeminiVersion, Model};
 use lazy_static::lazy_static;
 
 lazy_static! {
-    pub static ref MAP: HashMap<&'static str, Model> = ModelMetadata::map_providers();
+    pub static ref MAP: HashMap<String, Model> = ModelMetadata::map_providers();
 }
 
 /// The purpose of this mod is to encapsulate utility functions for maintainers of this crate
@@ -22,15 +22,17 @@ lazy_static! {
 pub async fn fetch_and_display_metadata() {
     check_claude().await;
     check_chatgpt().await;
+    check_gemini().await;
+    check_ollama().await;
 }
 
 async fn check_claude() {
-    let config = ModelConfigBuilder::new(Model::Claude(ClaudeVersion::None))
+    let config = ModelConfigBuilder::new(Model::Claude(ClaudeVersion::Sonnet4))
         .build()
         .unwrap();
     let client = reqwest::Client::new();
     let res = client
-        .get("https://api.anthropic.com/v1/models")
+        .get(config.model.to_models_url(&config.base_url()))
         .with_model_headers(&config)
         .send()
         .await
@@ -43,12 +45,12 @@ async fn check_claude() {
 }
 
 async fn check_chatgpt() {
-    let config = ModelConfigBuilder::new(Model::ChatGpt(ChatGptVersion::None))
+    let config = ModelConfigBuilder::new(Model::ChatGpt(ChatGptVersion::Gpt5))
         .build()
         .unwrap();
     let client = reqwest::Client::new();
     let res = client
-        .get("https://api.openai.com/v1/models")
+        .get(config.model.to_models_url(&config.base_url()))
         .with_model_headers(&config)
         .send()
         .await
@@ -60,6 +62,57 @@ async fn check_chatgpt() {
     metadata.display_unsupported_models();
 }
 
+async fn check_gemini() {
+    let config = ModelConfigBuilder::new(Model::Gemini(GeminiVersion::Gemini25Flash))
+        .build()
+        .unwrap();
+    let client = reqwest::Client::new();
+    let res = client
+        .get(config.model.to_models_url(&config.base_url()))
+        .with_model_headers(&config)
+        .send()
+        .await
+        .unwrap();
+
+    let content = res.text().await.unwrap();
+    let mut metadata: ModelList = serde_json::from_str::<GeminiModelList>(content.as_str())
+        .unwrap()
+        .into();
+    metadata.set_provider("Google".to_string());
+    metadata.display_unsupported_models();
+}
+
+// every pulled model is usable as-is, so list what's there rather than checking for unsupported ones
+async fn check_ollama() {
+    match fetch_ollama_models().await {
+        Ok(models) => info!("OLLAMA MODELS AVAILABLE LOCALLY: {models:?}"),
+        // unlike the hosted providers, a local server not running is routine
+        Err(e) => error!("COULD NOT REACH OLLAMA: {e}"),
+    }
+}
+
+/// Models pulled into the ollama server at BASE_URL_OLLAMA (or localhost), via /api/tags
+pub async fn fetch_ollama_models() -> Result<Vec<Model>, reqwest::Error> {
+    let config = ModelConfigBuilder::new(Model::Ollama(String::new()))
+        .build()
+        .unwrap();
+    let client = reqwest::Client::new();
+    let tags = client
+        .get(config.model.to_models_url(&config.base_url()))
+        .with_model_headers(&config)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<OllamaTagList>()
+        .await?;
+
+    Ok(tags
+        .models
+        .into_iter()
+        .map(|m| Model::Ollama(m.name))
+        .collect())
+}
+
 fn demodel() -> Option<Model> {
     None
 }
@@ -86,6 +139,49 @@ pub struct ModelList {
     provider: Option<String>, // need to initialize
 }
 
+// gemini lists models under a different key, with names prefixed by "models/"
+#[derive(Debug, Clone, Deserialize)]
+struct GeminiModelDescriptor {
+    name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GeminiModelList {
+    models: Vec<GeminiModelDescriptor>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct OllamaTag {
+    name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct OllamaTagList {
+    models: Vec<OllamaTag>,
+}
+
+impl From<GeminiModelList> for ModelList {
+    fn from(value: GeminiModelList) -> Self {
+        let data = value
+            .models
+            .into_iter()
+            .map(|m| ModelDescriptor {
+                id: m
+                    .name
+                    .strip_prefix("models/")
+                    .map(str::to_string)
+                    .unwrap_or(m.name),
+                internal_rep: None,
+            })
+            .collect();
+        ModelList {
+            data,
+            normalized: false,
+            provider: None,
+        }
+    }
+}
+
 impl ModelList {
     pub fn set_provider(&mut self, provider: String) {
         self.provider = Some(provider)
@@ -121,8 +217,8 @@ pub enum ModelMetadata {
 
 #[cfg(feature = "dev-tools")]
 impl ModelMetadata {
-    pub fn map_providers() -> HashMap<&'static str, Model> {
-        let mut map: HashMap<&'static str, Model> = HashMap::new();
+    pub fn map_providers() -> HashMap<String, Model> {
+        let mut map: HashMap<String, Model> = HashMap::new();
         for provider in Model::iter() {
             match provider {
                 Model::Claude(_) => {
@@ -134,6 +230,11 @@ impl ModelMetadata {
                 Model::Gemini(_) => {
                     Self::insert_versions(&mut map, GeminiVersion::iter(), Model::Gemini)
                 }
+                // no fixed catalog, the model id is whatever the server hosts
+                Model::OpenAiCompatible { .. }
+                | Model::AzureOpenAi { .. }
+                | Model::Ollama(_)
+                | Model::Custom { .. } => (),
                 Model::None => (),
                 _ => eprintln!("Add provider branch to maintenance map_providers"),
             }
@@ -141,17 +242,17 @@ impl ModelMetadata {
         map
     }
 
-    fn insert_versions<T, F>(
-        map: &mut HashMap<&'static str, Model>,
-        iter: impl Iterator<Item = T>,
-        f: F,
-    ) where
+    fn insert_versions<T, F>(map: &mut HashMap<String, Model>, iter: impl Iterator<Item = T>, f: F)
+    where
         F: Fn(T) -> Model,
     {
         for version in iter {
             let complete = f(version);
-            if let Some(model_str) = complete.to_model_string() {
-                map.insert(model_str, complete);
+            // custom versions iterate as an empty id, they have nothing to map
+            if let Some(model_str) = complete.to_model_string()
+                && !model_str.is_empty()
+            {
+                map.insert(model_str.to_string(), complete);
             }
         }
     }
@@ -0,0 +1,143 @@
+use std::sync::{LazyLock, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::Usage,
+    models::{ChatGptVersion, ClaudeVersion, GeminiVersion, Model},
+};
+
+/// Mod purpose:
+/// Price tokens so spend can be estimated & capped.
+/// Defaults are list prices at the time of writing & will drift; set_pricing overrides them (or prices a model we don't know) at runtime.
+
+#[allow(clippy::empty_line_after_doc_comments)]
+/// Holds runtime overrides, consulted before the defaults
+static OVERRIDES: LazyLock<RwLock<Vec<(Model, ModelPricing)>>> =
+    LazyLock::new(|| RwLock::new(Vec::new()));
+
+const PER_MILLION: f64 = 1_000_000.0;
+
+/// USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPricing {
+    pub input: f64,
+    pub output: f64,
+    pub cache_read: f64,
+    pub cache_write: f64,
+}
+
+impl ModelPricing {
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.input_tokens as f64 * self.input
+            + usage.output_tokens as f64 * self.output
+            + usage.cache_read_tokens as f64 * self.cache_read
+            + usage.cache_write_tokens as f64 * self.cache_write)
+            / PER_MILLION
+    }
+}
+
+pub fn set_pricing(model: Model, pricing: ModelPricing) {
+    let mut wguard = OVERRIDES.write().expect("Guard poisoned");
+    match wguard.iter_mut().find(|(m, _)| *m == model) {
+        Some((_, existing)) => *existing = pricing,
+        None => wguard.push((model, pricing)),
+    }
+}
+
+/// drops an override, falling back to the default (if any)
+pub fn reset_pricing(model: &Model) {
+    let mut wguard = OVERRIDES.write().expect("Guard poisoned");
+    wguard.retain(|(m, _)| m != model);
+}
+
+pub fn get_pricing(model: &Model) -> Option<ModelPricing> {
+    let rguard = OVERRIDES.read().expect("Guard poisoned");
+    rguard
+        .iter()
+        .find(|(m, _)| m == model)
+        .map(|(_, p)| *p)
+        .or_else(|| default_pricing(model))
+}
+
+fn default_pricing(model: &Model) -> Option<ModelPricing> {
+    let (input, output, cache_read, cache_write) = match model {
+        Model::Claude(ver) => match ver {
+            ClaudeVersion::Sonnet4 => (3.0, 15.0, 0.30, 3.75),
+            ClaudeVersion::Custom(_) | ClaudeVersion::None => return None,
+        },
+        // openai caches automatically, writes aren't billed
+        Model::ChatGpt(ver) => match ver {
+            ChatGptVersion::Gpt5 => (1.25, 10.0, 0.125, 0.0),
+            ChatGptVersion::Custom(_) | ChatGptVersion::None => return None,
+        },
+        // gemini pro prices long prompts (>200k tokens) higher, the short prompt tier is used here
+        Model::Gemini(ver) => match ver {
+            GeminiVersion::Gemini25Pro => (1.25, 10.0, 0.125, 0.0),
+            GeminiVersion::Gemini25Flash => (0.30, 2.50, 0.03, 0.0),
+            GeminiVersion::Gemini25FlashLite => (0.10, 0.40, 0.01, 0.0),
+            GeminiVersion::Custom(_) | GeminiVersion::None => return None,
+        },
+        // self-hosted or third party, register a price with set_pricing if spend matters
+        // deployments can sit on any model & azure contracts price them individually
+        Model::OpenAiCompatible { .. } | Model::AzureOpenAi { .. } => return None,
+        // runs on your own hardware
+        Model::Ollama(_) => (0.0, 0.0, 0.0, 0.0),
+        // register a price with set_pricing
+        Model::Custom { .. } => return None,
+        #[cfg(feature = "dev-tools")]
+        Model::None => return None,
+    };
+    Some(ModelPricing {
+        input,
+        output,
+        cache_read,
+        cache_write,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cost_prices_each_kind_of_token() {
+        let pricing = get_pricing(&Model::Claude(ClaudeVersion::Sonnet4)).unwrap();
+        let usage = Usage {
+            input_tokens: 1_000_000,
+            output_tokens: 100_000,
+            cache_read_tokens: 1_000_000,
+            cache_write_tokens: 200_000,
+            reasoning_tokens: 50_000,
+        };
+        // reasoning is already counted within output
+        assert!((pricing.cost(&usage) - (3.0 + 1.5 + 0.30 + 0.75)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn overrides_win_until_reset() {
+        let model = Model::Custom {
+            provider: "acme".to_string(),
+            model_id: "priced-1".to_string(),
+        };
+        let pricing = ModelPricing {
+            input: 1.0,
+            output: 2.0,
+            cache_read: 0.1,
+            cache_write: 0.0,
+        };
+        assert_eq!(get_pricing(&model), None);
+        set_pricing(model.clone(), pricing);
+        assert_eq!(get_pricing(&model), Some(pricing));
+        reset_pricing(&model);
+        assert_eq!(get_pricing(&model), None);
+
+        // an override replaces a default, resetting brings the default back
+        let gpt5 = Model::ChatGpt(ChatGptVersion::Gpt5);
+        let default = get_pricing(&gpt5);
+        set_pricing(gpt5.clone(), pricing);
+        assert_eq!(get_pricing(&gpt5), Some(pricing));
+        reset_pricing(&gpt5);
+        assert_eq!(get_pricing(&gpt5), default);
+    }
+}
@@ -0,0 +1,209 @@
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use reqwest::{RequestBuilder, Response};
+
+use crate::{
+    client::{LlmClientError, WithModelHeaders},
+    message::{
+        Message, MessageBundle, MessageError, StopReason, Usage,
+        serde::{
+            ModelRequestWrapper, ModelResponseWrapper, parse_error_body, stream::decode_stream_line,
+        },
+    },
+    models::ModelConfig,
+};
+
+pub use crate::message::serde::{ErrorBody, stream::StreamFrame};
+
+/// Mod purpose:
+/// The seam between LlmClient & the wire. Built-in models go through `Builtin`, which dispatches on `Model`;
+/// anything else is a `Model::Custom` routed to a provider registered with `LlmClient::with_provider`.
+
+#[allow(clippy::empty_line_after_outer_attr)]
+/// Everything `LlmClient` needs to talk to a backend; retries, budgets, usage totals & history stay with the client
+#[async_trait]
+pub trait Provider: Debug + Send + Sync {
+    /// Name a `Model::Custom { provider, .. }` refers to this provider by
+    fn name(&self) -> &str;
+
+    /// Url requests for `config` are posted to
+    fn endpoint(&self, config: &ModelConfig, stream: bool) -> String;
+
+    /// Attach auth & any other headers, called once per attempt so credentials can be fetched or refreshed
+    async fn headers(
+        &self,
+        config: &ModelConfig,
+        request: RequestBuilder,
+    ) -> Result<RequestBuilder, LlmClientError>;
+
+    /// Request body for `next`, following on from `history`
+    fn encode_request(
+        &self,
+        config: &ModelConfig,
+        history: &[MessageBundle],
+        next: &MessageBundle,
+        stream: bool,
+    ) -> Result<String, LlmClientError>;
+
+    /// Decode a successful response, error statuses never reach this
+    async fn decode_response(
+        &self,
+        config: &ModelConfig,
+        response: Response,
+    ) -> Result<ProviderResponse, LlmClientError>;
+
+    /// Decode an error body, None keeps the raw body as the error's message
+    fn decode_error(&self, _config: &ModelConfig, _body: &str) -> Option<ErrorBody> {
+        None
+    }
+
+    /// Response header carrying the backend's id for a request
+    fn request_id_header(&self, _config: &ModelConfig) -> Option<&str> {
+        None
+    }
+
+    fn supports_streaming(&self, _config: &ModelConfig) -> bool {
+        false
+    }
+
+    /// Whether decode_stream_line emits `StreamFrame::Done` for the end of a complete response;
+    /// if so a body ending before Done is treated as cut off, otherwise the end of the body is the end of the response
+    fn stream_has_end_marker(&self, _config: &ModelConfig) -> bool {
+        false
+    }
+
+    /// Decode one line of a streamed body, a line may carry several frames or none
+    fn decode_stream_line(
+        &self,
+        _config: &ModelConfig,
+        _line: &str,
+    ) -> Result<Vec<StreamFrame>, LlmClientError> {
+        Err(LlmClientError::Unsupported(format!(
+            "provider {:?} does not stream",
+            self.name()
+        )))
+    }
+}
+
+/// A decoded response, the client attaches metadata & records usage
+#[derive(Debug, Clone)]
+pub struct ProviderResponse {
+    pub message: Message,
+    pub usage: Option<Usage>,
+    pub stop_reason: Option<StopReason>,
+}
+
+/// The providers aipi ships with, dispatching on the config's `Model`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Builtin;
+
+impl Builtin {
+    /// Decode a response carrying several candidates, the provider reports usage once for all of them
+    pub(crate) async fn decode_candidates(
+        &self,
+        config: &ModelConfig,
+        response: Response,
+    ) -> Result<(Vec<ProviderResponse>, Option<Usage>), LlmClientError> {
+        let content = response
+            .text()
+            .await
+            .map_err(|e| LlmClientError::ExtractContent(e.to_string()))?;
+        let wrapped = ModelResponseWrapper::parse_new(content, config).map_err(codec_err)?;
+        let usage = wrapped.usage();
+        let candidates = wrapped
+            .into_candidates()
+            .into_iter()
+            .map(|(message, stop_reason)| ProviderResponse {
+                message,
+                usage: None,
+                stop_reason,
+            })
+            .collect();
+        Ok((candidates, usage))
+    }
+}
+
+#[async_trait]
+impl Provider for Builtin {
+    fn name(&self) -> &str {
+        "builtin"
+    }
+
+    fn endpoint(&self, config: &ModelConfig, stream: bool) -> String {
+        let base_url = config.base_url();
+        match stream {
+            true => config.model.to_stream_target_url(&base_url),
+            false => config.model.to_target_url(&base_url),
+        }
+    }
+
+    async fn headers(
+        &self,
+        config: &ModelConfig,
+        request: RequestBuilder,
+    ) -> Result<RequestBuilder, LlmClientError> {
+        Ok(request.with_model_headers(config))
+    }
+
+    fn encode_request(
+        &self,
+        config: &ModelConfig,
+        history: &[MessageBundle],
+        next: &MessageBundle,
+        stream: bool,
+    ) -> Result<String, LlmClientError> {
+        ModelRequestWrapper::new(config, history, next, stream)
+            .map(|wrapped| wrapped.to_payload())
+            .map_err(codec_err)
+    }
+
+    async fn decode_response(
+        &self,
+        config: &ModelConfig,
+        response: Response,
+    ) -> Result<ProviderResponse, LlmClientError> {
+        let content = response
+            .text()
+            .await
+            .map_err(|e| LlmClientError::ExtractContent(e.to_string()))?;
+        let wrapped = ModelResponseWrapper::parse_new(content, config).map_err(codec_err)?;
+        Ok(ProviderResponse {
+            usage: wrapped.usage(),
+            stop_reason: wrapped.stop_reason(),
+            message: Message::from(wrapped),
+        })
+    }
+
+    fn decode_error(&self, config: &ModelConfig, body: &str) -> Option<ErrorBody> {
+        parse_error_body(body, &config.model)
+    }
+
+    fn request_id_header(&self, config: &ModelConfig) -> Option<&str> {
+        config.model.to_request_id_header()
+    }
+
+    fn supports_streaming(&self, _config: &ModelConfig) -> bool {
+        true
+    }
+
+    // every built-in stream ends on an explicit event, or for gemini a chunk with a finish reason
+    fn stream_has_end_marker(&self, _config: &ModelConfig) -> bool {
+        true
+    }
+
+    fn decode_stream_line(
+        &self,
+        config: &ModelConfig,
+        line: &str,
+    ) -> Result<Vec<StreamFrame>, LlmClientError> {
+        decode_stream_line(line, &config.model).map_err(codec_err)
+    }
+}
+
+fn codec_err(e: MessageError) -> LlmClientError {
+    match e {
+        MessageError::Unsupported(message) => LlmClientError::Unsupported(message),
+        e => LlmClientError::ParseResponse(e.to_string()),
+    }
+}
@@ -0,0 +1,416 @@
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    client::{LlmClient, LlmClientError},
+    message::{ContentPart, Message},
+};
+
+/// Mod purpose:
+/// A small in-memory vector store for retrieval augmented chats: documents are embedded with an LlmClient
+/// on an embedding model, searched by similarity, and the hits spliced into the next message to a chat client.
+/// Search is a linear scan, which is plenty for the few thousand chunks of a docs bot; anything bigger wants a real vector db.
+
+#[allow(clippy::empty_line_after_doc_comments)]
+pub const INDEX_VERSION: u32 = 1;
+
+#[derive(Debug, Clone)]
+pub enum IndexError {
+    Io(String),
+    Serde(String),
+    UnsupportedVersion(u32),
+    /// every vector in an index, & every query against it, has the same length
+    DimensionMismatch {
+        expected: usize,
+        found: usize,
+    },
+    /// the document (by id) has no vector, see VectorIndex::embed_and_insert
+    NotEmbedded(String),
+    Embedding(LlmClientError),
+    /// the embedder answered with a different number of vectors than it was sent texts
+    EmbeddingCount {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl Display for IndexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{self:?}"))
+    }
+}
+
+impl Error for IndexError {}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Similarity {
+    #[default]
+    Cosine,
+    /// for vectors that are already normalized, or when magnitude should count.
+    /// openai's are at any length, gemini's only at full length: shortened with embedding_dimensions they aren't, use Cosine
+    DotProduct,
+}
+
+impl Similarity {
+    fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+        match self {
+            Similarity::DotProduct => dot,
+            Similarity::Cosine => {
+                let norms = norm(a) * norm(b);
+                // a zero vector is similar to nothing
+                if norms == 0.0 { 0.0 } else { dot / norms }
+            }
+        }
+    }
+}
+
+fn norm(v: &[f32]) -> f32 {
+    v.iter().map(|x| x * x).sum::<f32>().sqrt()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Document {
+    /// unique within an index, inserting an existing id replaces that document
+    pub id: String,
+    pub text: String,
+    #[serde(default)]
+    pub metadata: Value,
+    /// empty until embedded
+    #[serde(default)]
+    pub vector: Vec<f32>,
+}
+
+impl Document {
+    pub fn new(id: String, text: String) -> Document {
+        Document {
+            id,
+            text,
+            metadata: Value::Null,
+            vector: Vec::new(),
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: Value) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn with_vector(mut self, vector: Vec<f32>) -> Self {
+        self.vector = vector;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SearchHit<'a> {
+    pub document: &'a Document,
+    /// higher is more similar
+    pub score: f32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VectorIndex {
+    pub similarity: Similarity,
+    /// passed to LlmClient::embed when the index embeds documents & queries itself
+    pub embedding_dimensions: Option<usize>,
+    documents: Vec<Document>,
+}
+
+impl VectorIndex {
+    pub fn new(similarity: Similarity) -> VectorIndex {
+        VectorIndex {
+            similarity,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_embedding_dimensions(mut self, dimensions: usize) -> Self {
+        self.embedding_dimensions = Some(dimensions);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Length of every vector in the index, None while it's empty
+    pub fn dimensions(&self) -> Option<usize> {
+        self.documents.first().map(|d| d.vector.len())
+    }
+
+    pub fn documents(&self) -> &[Document] {
+        &self.documents
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Document> {
+        self.documents.iter().find(|d| d.id == id)
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<Document> {
+        let position = self.documents.iter().position(|d| d.id == id)?;
+        Some(self.documents.remove(position))
+    }
+
+    /// Add an already embedded document, replacing any with the same id
+    pub fn insert(&mut self, document: Document) -> Result<(), IndexError> {
+        if document.vector.is_empty() {
+            return Err(IndexError::NotEmbedded(document.id));
+        }
+        self.check_dimensions(document.vector.len())?;
+        match self.documents.iter_mut().find(|d| d.id == document.id) {
+            Some(existing) => *existing = document,
+            None => self.documents.push(document),
+        }
+        Ok(())
+    }
+
+    /// Embed the documents' text with `embedder` (a client on an embedding model) & insert them
+    pub async fn embed_and_insert(
+        &mut self,
+        embedder: &mut LlmClient,
+        documents: Vec<Document>,
+    ) -> Result<(), IndexError> {
+        let texts: Vec<&str> = documents.iter().map(|d| d.text.as_str()).collect();
+        let embeddings = embedder
+            .embed(&texts, self.embedding_dimensions)
+            .await
+            .map_err(IndexError::Embedding)?;
+        // zipping a short answer would leave documents out without a word
+        if embeddings.vectors.len() != documents.len() {
+            return Err(IndexError::EmbeddingCount {
+                expected: documents.len(),
+                found: embeddings.vectors.len(),
+            });
+        }
+        for (document, vector) in documents.into_iter().zip(embeddings.vectors) {
+            self.insert(document.with_vector(vector))?;
+        }
+        Ok(())
+    }
+
+    /// The k documents most similar to `vector`, most similar first
+    pub fn search(&self, vector: &[f32], k: usize) -> Result<Vec<SearchHit<'_>>, IndexError> {
+        if self.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.check_dimensions(vector.len())?;
+        let mut hits: Vec<SearchHit> = self
+            .documents
+            .iter()
+            .map(|document| SearchHit {
+                document,
+                score: self.similarity.score(vector, &document.vector),
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(k);
+        Ok(hits)
+    }
+
+    /// Embed `text` with `embedder` (the same model the documents were embedded with) & search for it
+    pub async fn query(
+        &self,
+        embedder: &mut LlmClient,
+        text: &str,
+        k: usize,
+    ) -> Result<Vec<SearchHit<'_>>, IndexError> {
+        let embeddings = embedder
+            .embed(&[text], self.embedding_dimensions)
+            .await
+            .map_err(IndexError::Embedding)?;
+        let [vector] = <[Vec<f32>; 1]>::try_from(embeddings.vectors).map_err(|vectors| {
+            IndexError::EmbeddingCount {
+                expected: 1,
+                found: vectors.len(),
+            }
+        })?;
+        self.search(&vector, k)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), IndexError> {
+        let mut writer = BufWriter::new(File::create(path.as_ref()).map_err(io_err)?);
+        let document = IndexDocument {
+            version: INDEX_VERSION,
+            index: Cow::Borrowed(self),
+        };
+        // vectors make pretty printing more noise than help
+        serde_json::to_writer(&mut writer, &document).map_err(serde_err)?;
+        writer.flush().map_err(io_err)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<VectorIndex, IndexError> {
+        let reader = BufReader::new(File::open(path.as_ref()).map_err(io_err)?);
+        let document: IndexDocument = serde_json::from_reader(reader).map_err(serde_err)?;
+        match document.version {
+            INDEX_VERSION => Ok(document.index.into_owned()),
+            v => Err(IndexError::UnsupportedVersion(v)),
+        }
+    }
+
+    fn check_dimensions(&self, found: usize) -> Result<(), IndexError> {
+        match self.dimensions() {
+            Some(expected) if expected != found => {
+                Err(IndexError::DimensionMismatch { expected, found })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexDocument<'a> {
+    version: u32,
+    index: Cow<'a, VectorIndex>,
+}
+
+fn io_err(e: std::io::Error) -> IndexError {
+    IndexError::Io(e.to_string())
+}
+
+fn serde_err(e: serde_json::Error) -> IndexError {
+    IndexError::Serde(e.to_string())
+}
+
+impl Message {
+    /// Put retrieved documents ahead of the message's own content, numbered so the AI can cite them
+    pub fn with_retrieved(mut self, hits: &[SearchHit]) -> Self {
+        if hits.is_empty() {
+            return self;
+        }
+        let context: String = hits
+            .iter()
+            .enumerate()
+            .map(|(i, hit)| {
+                format!(
+                    "[{}] ({})\n{}\n\n",
+                    i + 1,
+                    hit.document.id,
+                    hit.document.text
+                )
+            })
+            .collect();
+        let preamble = format!(
+            "Answer using the retrieved context below where it is relevant, citing it by number.\n\n<context>\n{}</context>\n\n",
+            context
+        );
+        // kept as one text part so plain text messages stay plain on the wire
+        match self.content.first_mut() {
+            Some(ContentPart::Text(text)) => text.insert_str(0, &preamble),
+            _ => self.content.insert(0, ContentPart::Text(preamble)),
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: &str, vector: &[f32]) -> Document {
+        Document::new(id.to_string(), format!("text of {id}")).with_vector(vector.to_vec())
+    }
+
+    fn index(similarity: Similarity) -> VectorIndex {
+        let mut index = VectorIndex::new(similarity);
+        index.insert(document("east", &[1.0, 0.0])).unwrap();
+        index.insert(document("north", &[0.0, 2.0])).unwrap();
+        index.insert(document("north_east", &[3.0, 3.0])).unwrap();
+        index
+    }
+
+    fn ids<'a>(hits: &[SearchHit<'a>]) -> Vec<&'a str> {
+        hits.iter().map(|h| h.document.id.as_str()).collect()
+    }
+
+    #[test]
+    fn cosine_ignores_magnitude() {
+        let cosine = Similarity::Cosine;
+        assert!((cosine.score(&[1.0, 0.0], &[5.0, 0.0]) - 1.0).abs() < 1e-6);
+        assert!(cosine.score(&[1.0, 0.0], &[0.0, 1.0]).abs() < 1e-6);
+        assert!((cosine.score(&[1.0, 0.0], &[-2.0, 0.0]) + 1.0).abs() < 1e-6);
+        assert!((cosine.score(&[1.0, 1.0], &[1.0, 0.0]) - 0.5f32.sqrt()).abs() < 1e-6);
+        assert_eq!(cosine.score(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn dot_product_counts_magnitude() {
+        assert_eq!(Similarity::DotProduct.score(&[1.0, 2.0], &[3.0, 4.0]), 11.0);
+        assert_eq!(Similarity::DotProduct.score(&[1.0, 0.0], &[5.0, 0.0]), 5.0);
+    }
+
+    #[test]
+    fn search_returns_the_k_most_similar_first() {
+        let index = index(Similarity::Cosine);
+        assert_eq!(
+            ids(&index.search(&[1.0, 0.1], 3).unwrap()),
+            ["east", "north_east", "north"]
+        );
+        assert_eq!(
+            ids(&index.search(&[0.1, 1.0], 2).unwrap()),
+            ["north", "north_east"]
+        );
+
+        let index = self::index(Similarity::DotProduct);
+        assert_eq!(ids(&index.search(&[1.0, 0.1], 1).unwrap()), ["north_east"]);
+    }
+
+    #[test]
+    fn vectors_must_match_the_index_dimensions() {
+        let mut index = index(Similarity::Cosine);
+        assert!(matches!(
+            index.search(&[1.0, 0.0, 0.0], 1),
+            Err(IndexError::DimensionMismatch {
+                expected: 2,
+                found: 3
+            })
+        ));
+        assert!(matches!(
+            index.insert(document("up", &[0.0, 0.0, 1.0])),
+            Err(IndexError::DimensionMismatch { .. })
+        ));
+        assert!(matches!(
+            index.insert(Document::new("bare".to_string(), String::new())),
+            Err(IndexError::NotEmbedded(_))
+        ));
+        // an existing id is replaced
+        index.insert(document("east", &[1.0, 1.0])).unwrap();
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.get("east").unwrap().vector, [1.0, 1.0]);
+    }
+
+    #[test]
+    fn saved_index_loads_back_only_at_its_version() {
+        let dir = std::env::temp_dir().join(format!("aipi-index-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("index.json");
+
+        let index = index(Similarity::DotProduct).with_embedding_dimensions(2);
+        index.save(&path).unwrap();
+        let loaded = VectorIndex::load(&path).unwrap();
+        assert_eq!(loaded.similarity, Similarity::DotProduct);
+        assert_eq!(loaded.embedding_dimensions, Some(2));
+        assert_eq!(loaded.documents(), index.documents());
+
+        let mut saved: Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        saved["version"] = Value::from(INDEX_VERSION + 1);
+        std::fs::write(&path, saved.to_string()).unwrap();
+        let result = VectorIndex::load(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(result, Err(IndexError::UnsupportedVersion(v)) if v == INDEX_VERSION + 1));
+    }
+}
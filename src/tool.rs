@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Mod purpose:
+/// Provider-neutral notion of tool (function) calling.
+/// Definitions are registered against a ModelConfig & serialized into each provider's request format,
+/// calls are decoded out of AI responses, and results are sent back as a message from Role::Tool.
+
+#[allow(clippy::empty_line_after_doc_comments)]
+/// A tool the model may call; input_schema is a JSON schema object describing the arguments
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub input_schema: Value,
+}
+
+impl ToolDefinition {
+    pub fn new(name: String, description: String, input_schema: Value) -> Self {
+        ToolDefinition {
+            name,
+            description,
+            input_schema,
+        }
+    }
+}
+
+/// A request from the model to invoke a tool
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// provider-assigned id used to pair the call with its result
+    /// (gemini & ollama do not assign ids, so one is made from the tool name & the call's position in the response)
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+impl ToolCall {
+    // unique within a response even when one tool is called several times in parallel,
+    // the providers needing it match results back up by name
+    pub(crate) fn positional_id(name: &str, position: usize) -> String {
+        format!("{name}-{position}")
+    }
+}
+
+/// The outcome of running a tool, to be returned to the model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolResult {
+    pub call_id: String,
+    pub name: String,
+    pub content: String,
+    pub is_error: bool,
+}
+
+impl ToolResult {
+    pub fn new(call: &ToolCall, content: String) -> Self {
+        ToolResult {
+            call_id: call.id.clone(),
+            name: call.name.clone(),
+            content,
+            is_error: false,
+        }
+    }
+
+    pub fn error(call: &ToolCall, content: String) -> Self {
+        ToolResult {
+            is_error: true,
+            ..ToolResult::new(call, content)
+        }
+    }
+}
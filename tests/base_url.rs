@@ -0,0 +1,53 @@
+mod support;
+
+use aipi::client::LlmClient;
+use aipi::environment::{BASE_URL_ANTHROPIC, BASE_URL_GOOGLE, BASE_URL_OLLAMA};
+use aipi::message::Message;
+use aipi::models::{ClaudeVersion, GeminiVersion, Model, ModelConfigBuilder};
+use support::{Reply, StandIn, keyless_config};
+
+// the env is read once per process, so everything depending on it lives in this one test
+#[tokio::test]
+async fn base_url_comes_from_the_config_then_the_env_then_the_default() {
+    let server = StandIn::start(vec![Reply::json(
+        "{\"message\":{\"role\":\"assistant\",\"content\":\"Hello!\"},\"done\":true,\"done_reason\":\"stop\"}",
+    )])
+    .await;
+    // SAFETY: the only test in this binary, nothing else reads or writes the env meanwhile
+    unsafe {
+        std::env::set_var(BASE_URL_OLLAMA, &server.base_url);
+        std::env::set_var(BASE_URL_ANTHROPIC, "http://proxy.internal/anthropic/v1");
+        std::env::remove_var(BASE_URL_GOOGLE);
+    }
+
+    // ollama builds without a key, so requests actually go to the env's url
+    let config = ModelConfigBuilder::new(Model::Ollama("llama3.2".to_string()))
+        .build()
+        .unwrap();
+    assert_eq!(config.base_url(), server.base_url);
+    let mut client = LlmClient::new(config);
+    client
+        .send_chat_message(Message::from_user("Hi".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(server.requests().len(), 1);
+
+    let mut config = keyless_config(
+        Model::Claude(ClaudeVersion::Sonnet4),
+        "http://localhost:9000/v1",
+    );
+    assert_eq!(config.base_url(), "http://localhost:9000/v1");
+    config.base_url = None;
+    assert_eq!(config.base_url(), "http://proxy.internal/anthropic/v1");
+
+    // nothing set anywhere
+    let mut config = keyless_config(
+        Model::Gemini(GeminiVersion::Gemini25Flash),
+        "http://localhost:9000/v1",
+    );
+    config.base_url = None;
+    assert_eq!(
+        config.base_url(),
+        "https://generativelanguage.googleapis.com/v1beta"
+    );
+}
@@ -0,0 +1,48 @@
+mod support;
+
+use aipi::client::{LlmClient, LlmClientError};
+use aipi::message::Message;
+use aipi::models::{Model, ModelConfigBuilder};
+use aipi::pricing::{ModelPricing, set_pricing};
+use support::{Reply, StandIn};
+
+#[tokio::test]
+async fn over_budget_request_is_never_sent() {
+    let server = StandIn::start(vec![Reply::json(
+        "{\"choices\":[{\"index\":0,\"finish_reason\":\"stop\",\"message\":{\"role\":\"assistant\",\"content\":\"Hello!\"}}]}",
+    )])
+    .await;
+    let model: Model = format!("openai-compatible:priced@{}", server.base_url)
+        .parse()
+        .unwrap();
+    // $1 per million tokens, so a full 1024 token answer alone projects to ~$0.001
+    set_pricing(
+        model.clone(),
+        ModelPricing {
+            input: 1.0,
+            output: 1.0,
+            cache_read: 0.0,
+            cache_write: 0.0,
+        },
+    );
+    let config = ModelConfigBuilder::new(model).build().unwrap();
+    let mut client = LlmClient::new(config).with_budget(0.0005);
+
+    let result = client
+        .send_chat_message(Message::from_user("Hi".to_string()))
+        .await;
+
+    match result {
+        Err(LlmClientError::BudgetExceeded {
+            budget,
+            spent,
+            projected,
+        }) => {
+            assert_eq!((budget, spent), (0.0005, 0.0));
+            assert!(projected > budget);
+        }
+        other => panic!("expected the budget to be exceeded, got {other:?}"),
+    }
+    assert!(server.requests().is_empty());
+    assert!(client.message_history.is_empty());
+}
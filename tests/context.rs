@@ -0,0 +1,192 @@
+mod support;
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use aipi::client::{LlmClient, LlmClientError};
+use aipi::context::{ContextStrategy, KeepLastTurns, Selection, Summarize};
+use aipi::message::{Message, MessageBundle, MessageMetadata};
+use aipi::models::{Model, ModelConfigBuilder};
+use async_trait::async_trait;
+use serde_json::Value;
+use support::{Reply, StandIn};
+
+fn ollama_reply(text: &str) -> Reply {
+    Reply::json(format!(
+        "{{\"message\":{{\"role\":\"assistant\",\"content\":\"{text}\"}},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":4,\"eval_count\":1}}"
+    ))
+}
+
+fn ollama_client(base_url: &str) -> LlmClient {
+    let config = ModelConfigBuilder::new(Model::Ollama("llama3.2".to_string()))
+        .with_base_url(base_url.to_string())
+        .build()
+        .unwrap();
+    LlmClient::new(config)
+}
+
+fn history(client: &LlmClient, turns: &[&str]) -> Vec<MessageBundle> {
+    turns
+        .iter()
+        .flat_map(|text| {
+            [
+                Message::from_user(text.to_string()),
+                Message::from_ai(format!("re: {text}")),
+            ]
+        })
+        .map(|m| MessageBundle::new(m, MessageMetadata::new(&client.config)))
+        .collect()
+}
+
+// (role, text) of every message in a request body
+fn sent(request: &str) -> Vec<(String, String)> {
+    let body: Value = serde_json::from_str(request).unwrap();
+    body["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| {
+            (
+                m["role"].as_str().unwrap().to_string(),
+                m["content"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn summary_of_every_turn_goes_in_the_next_message() {
+    let summarizer = StandIn::start(vec![ollama_reply("they said hi twice")]).await;
+    let chat = StandIn::start(vec![ollama_reply("hello again")]).await;
+    let strategy = Summarize::new(ollama_client(&summarizer.base_url), 0);
+    let mut client = ollama_client(&chat.base_url).with_context_strategy(strategy.clone());
+    client.message_history = history(&client, &["hi", "hi again"]);
+
+    client
+        .send_chat_message(Message::from_user("and again".to_string()))
+        .await
+        .unwrap();
+
+    // one user message, not the summary & the message back to back
+    let sent = sent(&chat.requests()[0]);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].0, "user");
+    assert!(sent[0].1.contains("they said hi twice"));
+    assert!(sent[0].1.ends_with("and again"));
+    // the client's history keeps the message as it was written
+    assert_eq!(client.message_history.len(), 6);
+    assert_eq!(client.message_history[4].message.text(), "and again");
+    assert_eq!(
+        strategy.summary().await.as_deref(),
+        Some("they said hi twice")
+    );
+}
+
+#[tokio::test]
+async fn replaced_history_is_summarized_afresh() {
+    let summarizer = StandIn::start(vec![
+        ollama_reply("first chat"),
+        ollama_reply("second chat"),
+    ])
+    .await;
+    let chat = StandIn::start(vec![ollama_reply("ok"), ollama_reply("ok")]).await;
+    let strategy = Summarize::new(ollama_client(&summarizer.base_url), 1);
+    let mut client = ollama_client(&chat.base_url).with_context_strategy(strategy.clone());
+    client.message_history = history(&client, &["apples", "pears"]);
+    client
+        .send_chat_message(Message::from_user("plums".to_string()))
+        .await
+        .unwrap();
+
+    // as long as the first, so only a check of what's covered tells them apart
+    client.message_history = history(&client, &["cars", "boats", "trains"]);
+    client
+        .send_chat_message(Message::from_user("planes".to_string()))
+        .await
+        .unwrap();
+
+    let requests = summarizer.requests();
+    assert_eq!(requests.len(), 2);
+    let second = &sent(&requests[1])[0].1;
+    assert!(second.contains("cars") && second.contains("boats"));
+    assert!(!second.contains("previous_summary") && !second.contains("apples"));
+    assert_eq!(strategy.summary().await.as_deref(), Some("second chat"));
+    let window = sent(&chat.requests()[1]);
+    assert!(
+        window[0]
+            .1
+            .starts_with("Summary of the earlier conversation:\nsecond chat")
+    );
+}
+
+// sends history as is, counting how often it's asked
+#[derive(Debug, Default)]
+struct Counting(AtomicUsize);
+
+#[async_trait]
+impl ContextStrategy for Counting {
+    async fn select(
+        &self,
+        history: &[MessageBundle],
+        _next: &MessageBundle,
+    ) -> Result<Selection, LlmClientError> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Ok(history.to_vec().into())
+    }
+}
+
+#[tokio::test]
+async fn candidates_share_one_selection() {
+    let chat = StandIn::start(vec![
+        ollama_reply("one"),
+        ollama_reply("two"),
+        ollama_reply("three"),
+    ])
+    .await;
+    let strategy = Arc::new(Counting::default());
+    let mut client = ollama_client(&chat.base_url);
+    client.context_strategy = Some(strategy.clone());
+
+    // ollama can't return several candidates at once, so these are three requests
+    let candidates = client
+        .send_candidates(Message::from_user("hi".to_string()), 3)
+        .await
+        .unwrap();
+
+    assert_eq!(candidates.responses.len(), 3);
+    assert_eq!(chat.requests().len(), 3);
+    assert_eq!(strategy.0.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn continuing_a_response_keeps_the_turn_it_continues() {
+    let chat = StandIn::start(vec![
+        Reply::json(
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"the first half\"},\"done\":true,\"done_reason\":\"length\",\"prompt_eval_count\":4,\"eval_count\":1}",
+        ),
+        ollama_reply(" & the rest"),
+    ])
+    .await;
+    let mut client = ollama_client(&chat.base_url)
+        .with_auto_continue(1)
+        .with_context_strategy(KeepLastTurns(0));
+    client.message_history = history(&client, &["hi"]);
+
+    client
+        .send_chat_message(Message::from_user("tell me".to_string()))
+        .await
+        .unwrap();
+
+    // the continue prompt starts a turn of its own, yet the one it picks up goes with it
+    let sent = sent(&chat.requests()[1]);
+    let texts: Vec<&str> = sent.iter().map(|(_, text)| text.as_str()).collect();
+    assert_eq!(texts[..2], ["tell me", "the first half"]);
+    assert_eq!(sent.len(), 3);
+    assert_eq!(
+        client.message_history[3].message.text(),
+        "the first half & the rest"
+    );
+    assert!(!client.message_history[3].metadata.pinned());
+}
@@ -0,0 +1,43 @@
+mod support;
+
+use aipi::chunking::{Chunker, MapReduce, MapReduceProgress};
+use aipi::client::LlmClient;
+use aipi::models::{Model, ModelConfigBuilder};
+use support::{Reply, StandIn};
+
+const OLLAMA_REPLY: &str = "{\"message\":{\"role\":\"assistant\",\"content\":\"an answer\"},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":10,\"eval_count\":2}";
+
+#[tokio::test]
+async fn answers_too_long_for_one_prompt_are_reduced_in_rounds() {
+    let server = StandIn::start((0..7).map(|_| Reply::json(OLLAMA_REPLY)).collect()).await;
+    let config = ModelConfigBuilder::new(Model::Ollama("llama3.2".to_string()))
+        .with_base_url(server.base_url.clone())
+        .build()
+        .unwrap();
+    let mut client = LlmClient::new(config);
+    let chunks =
+        Chunker::Paragraphs { max_chars: 10 }.chunk("one\n\ntwo two\n\nthree\n\nfour four");
+    assert_eq!(chunks.len(), 4);
+
+    let mut rounds = Vec::new();
+    let output = MapReduce::new("Summarize.".to_string(), "Merge.".to_string())
+        .with_max_reduce_chars(10)
+        .run(&mut client, &chunks, |progress| {
+            if let MapReduceProgress::Reducing { partials } = progress {
+                rounds.push(partials);
+            }
+        })
+        .await
+        .unwrap();
+
+    // 4 chunks, 2 reduces over pairs of them, then one over those 2
+    assert_eq!(rounds, [4, 2]);
+    let requests = server.requests();
+    assert_eq!(requests.len(), 7);
+    assert!(requests[4..6].iter().all(|r| r.contains("Merge.")));
+    let last = &requests[6];
+    assert!(last.contains("[1-2]") && last.contains("[3-4]"));
+    assert_eq!(output.answer.message.text(), "an answer");
+    assert_eq!(output.usage.unwrap().input_tokens, 70);
+    assert!(client.message_history.is_empty());
+}
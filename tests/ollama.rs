@@ -0,0 +1,73 @@
+mod support;
+
+use aipi::client::{LlmClient, LlmClientError};
+use aipi::message::{Message, StopReason};
+use aipi::models::{Model, ModelConfigBuilder};
+use serde_json::{Value, json};
+use support::{Reply, StandIn};
+
+fn ollama_client(base_url: &str) -> LlmClient {
+    let config = ModelConfigBuilder::new(Model::Ollama("llama3.2".to_string()))
+        .with_base_url(base_url.to_string())
+        .with_temperature(0.2)
+        .build()
+        .unwrap();
+    LlmClient::new(config)
+}
+
+#[tokio::test]
+async fn chat_goes_to_the_native_api_without_a_key() {
+    let server = StandIn::start(vec![Reply::json(
+        "{\"model\":\"llama3.2\",\"message\":{\"role\":\"assistant\",\"content\":\"Hello!\"},\"done\":true,\"done_reason\":\"length\",\"prompt_eval_count\":9,\"eval_count\":3}",
+    )])
+    .await;
+    let mut client = ollama_client(&server.base_url);
+
+    client
+        .send_chat_message(Message::from_user("Hi".to_string()))
+        .await
+        .unwrap();
+
+    let request: Value = serde_json::from_str(&server.requests()[0]).unwrap();
+    assert_eq!(
+        request,
+        json!({
+            "model": "llama3.2",
+            "stream": false,
+            "options": {"temperature": 0.2, "num_predict": 1024},
+            "messages": [{"role": "user", "content": "Hi"}],
+        })
+    );
+    let reply = &client.message_history[1];
+    assert_eq!(reply.message.text(), "Hello!");
+    assert_eq!(reply.stop_reason(), Some(&StopReason::MaxTokens));
+    let usage = reply.usage().unwrap();
+    assert_eq!((usage.input_tokens, usage.output_tokens), (9, 3));
+}
+
+#[tokio::test]
+async fn error_body_message_is_kept() {
+    let server = StandIn::start(vec![Reply::status(
+        404,
+        "{\"error\":\"model \\\"llama9\\\" not found, try pulling it first\"}",
+    )])
+    .await;
+    let mut client = ollama_client(&server.base_url);
+
+    let result = client
+        .send_chat_message(Message::from_user("Hi".to_string()))
+        .await;
+
+    match result {
+        Err(LlmClientError::Provider(error)) => {
+            assert_eq!(error.status.as_u16(), 404);
+            assert_eq!(
+                error.message,
+                "model \"llama9\" not found, try pulling it first"
+            );
+            assert!(!error.is_retryable());
+        }
+        other => panic!("expected a provider error, got {other:?}"),
+    }
+    assert!(client.message_history.is_empty());
+}
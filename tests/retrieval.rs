@@ -0,0 +1,86 @@
+mod support;
+
+use aipi::client::{LlmClient, LlmClientError};
+use aipi::models::{Model, ModelConfigBuilder};
+use aipi::retrieval::{Document, IndexError, Similarity, VectorIndex};
+use support::{Reply, StandIn};
+
+fn embedder(base_url: &str) -> LlmClient {
+    let model: Model = format!("openai-compatible:nomic-embed@{base_url}")
+        .parse()
+        .unwrap();
+    LlmClient::new(ModelConfigBuilder::new(model).build().unwrap())
+}
+
+fn documents() -> Vec<Document> {
+    vec![
+        Document::new("a".to_string(), "first".to_string()),
+        Document::new("b".to_string(), "second".to_string()),
+    ]
+}
+
+#[tokio::test]
+async fn documents_are_embedded_in_input_order() {
+    // the provider may list vectors out of order, their index says whose they are
+    let server = StandIn::start(vec![Reply::json(
+        "{\"data\":[{\"index\":1,\"embedding\":[0.0,1.0]},{\"index\":0,\"embedding\":[1.0,0.0]}],\"usage\":{\"prompt_tokens\":4}}",
+    )])
+    .await;
+    let mut index = VectorIndex::new(Similarity::Cosine);
+
+    index
+        .embed_and_insert(&mut embedder(&server.base_url), documents())
+        .await
+        .unwrap();
+
+    assert_eq!(index.get("a").unwrap().vector, [1.0, 0.0]);
+    assert_eq!(index.get("b").unwrap().vector, [0.0, 1.0]);
+}
+
+#[tokio::test]
+async fn a_short_embedding_answer_is_an_error() {
+    let server = StandIn::start(vec![Reply::json(
+        "{\"data\":[{\"index\":0,\"embedding\":[1.0,0.0]}],\"usage\":{\"prompt_tokens\":4}}",
+    )])
+    .await;
+    let mut index = VectorIndex::new(Similarity::Cosine);
+
+    let result = index
+        .embed_and_insert(&mut embedder(&server.base_url), documents())
+        .await;
+
+    assert!(matches!(
+        result,
+        Err(IndexError::Embedding(LlmClientError::ParseResponse(_)))
+    ));
+    assert!(index.is_empty());
+}
+
+#[tokio::test]
+async fn a_duplicated_embedding_index_is_an_error() {
+    // two vectors for two inputs, but both claim the first one
+    let server = StandIn::start(vec![Reply::json(
+        "{\"data\":[{\"index\":0,\"embedding\":[1.0,0.0]},{\"index\":0,\"embedding\":[0.0,1.0]}],\"usage\":{\"prompt_tokens\":4}}",
+    )])
+    .await;
+
+    let result = embedder(&server.base_url)
+        .embed(&["first", "second"], None)
+        .await;
+
+    assert!(matches!(result, Err(LlmClientError::ParseResponse(_))));
+}
+
+#[tokio::test]
+async fn an_embedding_index_out_of_range_is_an_error() {
+    let server = StandIn::start(vec![Reply::json(
+        "{\"data\":[{\"index\":0,\"embedding\":[1.0,0.0]},{\"index\":2,\"embedding\":[0.0,1.0]}],\"usage\":{\"prompt_tokens\":4}}",
+    )])
+    .await;
+
+    let result = embedder(&server.base_url)
+        .embed(&["first", "second"], None)
+        .await;
+
+    assert!(matches!(result, Err(LlmClientError::ParseResponse(_))));
+}
@@ -0,0 +1,91 @@
+mod support;
+
+use std::time::{Duration, Instant};
+
+use aipi::client::{LlmClient, LlmClientError, retry::RetryPolicy};
+use aipi::message::Message;
+use aipi::models::{Model, ModelConfigBuilder};
+use support::{Reply, StandIn};
+
+const OLLAMA_REPLY: &str = "{\"message\":{\"role\":\"assistant\",\"content\":\"Hello\"},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":4,\"eval_count\":1}";
+
+fn ollama_client(base_url: &str) -> LlmClient {
+    let config = ModelConfigBuilder::new(Model::Ollama("llama3.2".to_string()))
+        .with_base_url(base_url.to_string())
+        .build()
+        .unwrap();
+    LlmClient::new(config).with_retry_policy(RetryPolicy {
+        base_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(200),
+        ..RetryPolicy::default()
+    })
+}
+
+#[tokio::test]
+async fn throttled_request_is_retried() {
+    let server = StandIn::start(vec![
+        Reply::status(429, "{\"error\":\"slow down\"}").with_header("retry-after", "0"),
+        Reply::json(OLLAMA_REPLY),
+    ])
+    .await;
+    let mut client = ollama_client(&server.base_url);
+
+    client
+        .send_chat_message(Message::from_user("Hi".to_string()))
+        .await
+        .unwrap();
+
+    assert_eq!(server.requests().len(), 2);
+    assert_eq!(client.message_history[1].message.text(), "Hello");
+}
+
+#[tokio::test]
+async fn retry_after_is_capped_at_max_delay() {
+    let server = StandIn::start(vec![
+        Reply::status(503, "{\"error\":\"busy\"}").with_header("retry-after", "3600"),
+        Reply::json(OLLAMA_REPLY),
+    ])
+    .await;
+    let mut client = ollama_client(&server.base_url);
+
+    let started = Instant::now();
+    client
+        .send_chat_message(Message::from_user("Hi".to_string()))
+        .await
+        .unwrap();
+
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn dropped_connection_after_sending_is_not_retried() {
+    // the request went out, so the provider may already be generating (& billing) it
+    let server = StandIn::start(vec![Reply::hang_up(), Reply::json(OLLAMA_REPLY)]).await;
+    let mut client = ollama_client(&server.base_url);
+
+    let result = client
+        .send_chat_message(Message::from_user("Hi".to_string()))
+        .await;
+
+    assert!(matches!(result, Err(LlmClientError::Request(_))));
+    assert_eq!(server.requests().len(), 1);
+    assert_eq!(client.message_history.len(), 0);
+}
+
+#[tokio::test]
+async fn client_errors_are_not_retried() {
+    let server = StandIn::start(vec![
+        Reply::status(400, "{\"error\":\"bad request\"}"),
+        Reply::json(OLLAMA_REPLY),
+    ])
+    .await;
+    let mut client = ollama_client(&server.base_url);
+
+    let result = client
+        .send_chat_message(Message::from_user("Hi".to_string()))
+        .await;
+
+    assert!(matches!(result, Err(LlmClientError::Provider(_))));
+    assert_eq!(server.requests().len(), 1);
+}
@@ -0,0 +1,220 @@
+mod support;
+
+use aipi::client::{LlmClient, LlmClientError};
+use aipi::message::{ContentPart, Message, StopReason, StreamDelta};
+use aipi::models::{GeminiVersion, Model, ModelConfigBuilder};
+use futures::StreamExt;
+use support::{Reply, StandIn, keyless_config};
+
+fn ollama_client(base_url: &str) -> LlmClient {
+    let config = ModelConfigBuilder::new(Model::Ollama("llama3.2".to_string()))
+        .with_base_url(base_url.to_string())
+        .build()
+        .unwrap();
+    LlmClient::new(config)
+}
+
+#[tokio::test]
+async fn complete_stream_is_committed_to_history() {
+    let server = StandIn::start(vec![Reply::json(concat!(
+        "{\"message\":{\"content\":\"Hel\"},\"done\":false}\n",
+        "{\"message\":{\"content\":\"lo\"},\"done\":false}\n",
+        "{\"message\":{\"content\":\"\"},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":4,\"eval_count\":2}\n",
+    ))])
+    .await;
+    let mut client = ollama_client(&server.base_url);
+
+    let mut stream = client
+        .send_chat_message_stream(Message::from_user("Hi".to_string()))
+        .await
+        .unwrap();
+    let mut text = String::new();
+    while let Some(delta) = stream.next().await {
+        if let StreamDelta::Text(t) = delta.unwrap() {
+            text.push_str(&t);
+        }
+    }
+    drop(stream);
+
+    assert_eq!(text, "Hello");
+    assert_eq!(client.message_history.len(), 2);
+    assert_eq!(client.message_history[1].message.text(), "Hello");
+}
+
+#[tokio::test]
+async fn stream_cut_off_before_its_end_marker_is_an_error() {
+    // the connection drops before the done chunk
+    let server = StandIn::start(vec![Reply::json(
+        "{\"message\":{\"content\":\"Hel\"},\"done\":false}\n",
+    )])
+    .await;
+    let mut client = ollama_client(&server.base_url);
+
+    let mut stream = client
+        .send_chat_message_stream(Message::from_user("Hi".to_string()))
+        .await
+        .unwrap();
+    let mut results = Vec::new();
+    while let Some(result) = stream.next().await {
+        results.push(result);
+    }
+    drop(stream);
+
+    assert!(matches!(
+        results.last(),
+        Some(Err(LlmClientError::ExtractContent(_)))
+    ));
+    assert!(client.message_history.is_empty());
+}
+
+#[tokio::test]
+async fn gemini_stream_cut_off_before_its_finish_reason_is_an_error() {
+    // gemini's last chunk carries the finish reason, the connection drops before it
+    let server = StandIn::start(vec![Reply::json(
+        "data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\"Hel\"}]}}]}\n\n",
+    )])
+    .await;
+    let config = keyless_config(
+        Model::Gemini(GeminiVersion::Gemini25Flash),
+        &server.base_url,
+    );
+    let mut client = LlmClient::new(config);
+
+    let mut stream = client
+        .send_chat_message_stream(Message::from_user("Hi".to_string()))
+        .await
+        .unwrap();
+    let mut results = Vec::new();
+    while let Some(result) = stream.next().await {
+        results.push(result);
+    }
+    drop(stream);
+
+    assert!(matches!(
+        results.last(),
+        Some(Err(LlmClientError::ExtractContent(_)))
+    ));
+    assert!(client.message_history.is_empty());
+}
+
+#[tokio::test]
+async fn streamed_tool_call_fragments_are_assembled() {
+    let events = [
+        r#"{"choices":[{"index":0,"delta":{"role":"assistant","content":null,"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"get_weather","arguments":""}}]},"finish_reason":null}]}"#,
+        r#"{"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"city\": "}}]},"finish_reason":null}]}"#,
+        r#"{"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"Oslo\"}"}}]},"finish_reason":null}]}"#,
+        r#"{"choices":[{"index":0,"delta":{},"finish_reason":"tool_calls"}]}"#,
+        "[DONE]",
+    ];
+    let body: String = events.iter().map(|e| format!("data: {e}\n\n")).collect();
+    let server = StandIn::start(vec![Reply::json(body)]).await;
+    let model: Model = format!("openai-compatible:qwen3@{}", server.base_url)
+        .parse()
+        .unwrap();
+    let mut client = LlmClient::new(ModelConfigBuilder::new(model).build().unwrap());
+
+    let mut stream = client
+        .send_chat_message_stream(Message::from_user("Weather in Oslo?".to_string()))
+        .await
+        .unwrap();
+    let mut calls = Vec::new();
+    while let Some(delta) = stream.next().await {
+        if let StreamDelta::ToolCall(call) = delta.unwrap() {
+            calls.push(call);
+        }
+    }
+    drop(stream);
+
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].id, "call_1");
+    assert_eq!(calls[0].name, "get_weather");
+    assert_eq!(calls[0].arguments, serde_json::json!({"city": "Oslo"}));
+    let response = &client.message_history[1];
+    assert_eq!(response.message.tool_calls(), vec![&calls[0]]);
+    assert_eq!(response.stop_reason(), Some(&StopReason::ToolUse));
+}
+
+#[tokio::test]
+async fn whole_streamed_tool_calls_are_kept() {
+    let server = StandIn::start(vec![Reply::json(concat!(
+        "{\"message\":{\"content\":\"\",\"tool_calls\":[{\"function\":{\"name\":\"get_weather\",\"arguments\":{\"city\":\"Oslo\"}}}]},\"done\":false}\n",
+        "{\"message\":{\"content\":\"\"},\"done\":true,\"done_reason\":\"stop\"}\n",
+    ))])
+    .await;
+    let mut client = ollama_client(&server.base_url);
+
+    let mut stream = client
+        .send_chat_message_stream(Message::from_user("Weather in Oslo?".to_string()))
+        .await
+        .unwrap();
+    while let Some(delta) = stream.next().await {
+        delta.unwrap();
+    }
+    drop(stream);
+
+    let content = &client.message_history[1].message.content;
+    assert!(matches!(
+        content.as_slice(),
+        [ContentPart::ToolUse(call)] if call.name == "get_weather" && call.arguments["city"] == "Oslo"
+    ));
+}
+
+#[tokio::test]
+async fn whole_streamed_calls_of_one_tool_get_distinct_ids() {
+    // ollama doesn't id calls, & these arrive in separate chunks
+    let server = StandIn::start(vec![Reply::json(concat!(
+        "{\"message\":{\"content\":\"\",\"tool_calls\":[{\"function\":{\"name\":\"get_weather\",\"arguments\":{\"city\":\"Oslo\"}}}]},\"done\":false}\n",
+        "{\"message\":{\"content\":\"\",\"tool_calls\":[{\"function\":{\"name\":\"get_weather\",\"arguments\":{\"city\":\"Bergen\"}}}]},\"done\":false}\n",
+        "{\"message\":{\"content\":\"\"},\"done\":true,\"done_reason\":\"stop\"}\n",
+    ))])
+    .await;
+    let mut client = ollama_client(&server.base_url);
+
+    let mut stream = client
+        .send_chat_message_stream(Message::from_user("Weather in Oslo & Bergen?".to_string()))
+        .await
+        .unwrap();
+    let mut ids = Vec::new();
+    while let Some(delta) = stream.next().await {
+        if let StreamDelta::ToolCall(call) = delta.unwrap() {
+            ids.push(call.id);
+        }
+    }
+    drop(stream);
+
+    assert_eq!(ids, ["get_weather-0", "get_weather-1"]);
+}
+
+#[tokio::test]
+async fn gemini_call_followed_by_a_plain_stop_is_a_tool_use() {
+    let events = [
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"functionCall":{"name":"get_weather","args":{"city":"Oslo"}}}]}}]}"#,
+        r#"{"candidates":[{"content":{"role":"model","parts":[{"text":""}]},"finishReason":"STOP"}],"usageMetadata":{"promptTokenCount":9,"candidatesTokenCount":5}}"#,
+    ];
+    let body: String = events
+        .iter()
+        .map(|e| format!("data: {e}\r\n\r\n"))
+        .collect();
+    let server = StandIn::start(vec![Reply::json(body)]).await;
+    let config = keyless_config(
+        Model::Gemini(GeminiVersion::Gemini25Flash),
+        &server.base_url,
+    );
+    let mut client = LlmClient::new(config);
+
+    let mut stream = client
+        .send_chat_message_stream(Message::from_user("Weather in Oslo?".to_string()))
+        .await
+        .unwrap();
+    while let Some(delta) = stream.next().await {
+        delta.unwrap();
+    }
+    drop(stream);
+
+    let response = &client.message_history[1];
+    assert_eq!(response.stop_reason(), Some(&StopReason::ToolUse));
+    assert!(matches!(
+        response.message.tool_calls().as_slice(),
+        [call] if call.id == "get_weather-0" && call.arguments["city"] == "Oslo"
+    ));
+}
@@ -0,0 +1,123 @@
+#![allow(dead_code)]
+
+use std::sync::{Arc, Mutex};
+
+use aipi::models::{Model, ModelConfig};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// A canned reply from the stand-in server
+pub struct Reply {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: String,
+    /// close the connection without answering, as a server dropping mid generation would
+    pub hang_up: bool,
+}
+
+impl Reply {
+    pub fn json(body: impl Into<String>) -> Reply {
+        Reply::status(200, body)
+    }
+
+    pub fn status(status: u16, body: impl Into<String>) -> Reply {
+        Reply {
+            status,
+            headers: vec![("content-type", "application/json".to_string())],
+            body: body.into(),
+            hang_up: false,
+        }
+    }
+
+    pub fn hang_up() -> Reply {
+        Reply {
+            hang_up: true,
+            ..Reply::status(200, "")
+        }
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+}
+
+/// A config for `model` sent to `base_url`, skipping the env lookup build does for an api key
+pub fn keyless_config(model: Model, base_url: &str) -> ModelConfig {
+    serde_json::from_value(serde_json::json!({
+        "model": model,
+        "max_tokens": 1024,
+        "tools": [],
+        "base_url": base_url,
+    }))
+    .unwrap()
+}
+
+/// Stand-in http server answering successive requests with `replies` in order;
+/// bodies are written without a length & the connection closed after, so a body may end anywhere (e.g. mid-stream)
+pub struct StandIn {
+    pub base_url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl StandIn {
+    pub async fn start(replies: Vec<Reply>) -> StandIn {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for reply in replies {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let body = read_request_body(&mut socket).await;
+                received.lock().unwrap().push(body);
+                if reply.hang_up {
+                    continue;
+                }
+
+                let mut head =
+                    format!("HTTP/1.1 {} STANDIN\r\nconnection: close\r\n", reply.status);
+                for (name, value) in reply.headers {
+                    head.push_str(&format!("{name}: {value}\r\n"));
+                }
+                head.push_str("\r\n");
+                socket.write_all(head.as_bytes()).await.unwrap();
+                socket.write_all(reply.body.as_bytes()).await.unwrap();
+                socket.shutdown().await.unwrap();
+            }
+        });
+        StandIn { base_url, requests }
+    }
+
+    /// Bodies of the requests received so far
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn read_request_body(socket: &mut tokio::net::TcpStream) -> String {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        let n = socket.read(&mut chunk).await.unwrap();
+        buffer.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        assert!(n > 0, "connection closed mid request");
+    };
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_lowercase();
+    let length: usize = head
+        .lines()
+        .find_map(|l| l.strip_prefix("content-length:"))
+        .map(|v| v.trim().parse().unwrap())
+        .unwrap_or_default();
+    while buffer.len() < header_end + length {
+        let n = socket.read(&mut chunk).await.unwrap();
+        assert!(n > 0, "connection closed mid body");
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+    String::from_utf8_lossy(&buffer[header_end..header_end + length]).to_string()
+}
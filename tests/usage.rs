@@ -0,0 +1,46 @@
+mod support;
+
+use aipi::client::LlmClient;
+use aipi::message::Message;
+use aipi::models::{Model, ModelConfigBuilder};
+use support::{Reply, StandIn};
+
+fn reply(text: &str, input_tokens: u64, output_tokens: u64) -> Reply {
+    Reply::json(format!(
+        "{{\"message\":{{\"role\":\"assistant\",\"content\":\"{text}\"}},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":{input_tokens},\"eval_count\":{output_tokens}}}"
+    ))
+}
+
+#[tokio::test]
+async fn every_response_adds_to_the_running_total() {
+    let server = StandIn::start(vec![reply("Hello!", 9, 3), reply("Bye!", 20, 2)]).await;
+    let config = ModelConfigBuilder::new(Model::Ollama("llama3.2".to_string()))
+        .with_base_url(server.base_url.clone())
+        .build()
+        .unwrap();
+    let mut client = LlmClient::new(config);
+
+    for text in ["Hi", "Bye"] {
+        client
+            .send_chat_message(Message::from_user(text.to_string()))
+            .await
+            .unwrap();
+    }
+
+    let per_response: Vec<(u64, u64)> = client
+        .message_history
+        .iter()
+        .filter_map(|b| b.usage())
+        .map(|u| (u.input_tokens, u.output_tokens))
+        .collect();
+    assert_eq!(per_response, [(9, 3), (20, 2)]);
+    assert_eq!(
+        (
+            client.total_usage.input_tokens,
+            client.total_usage.output_tokens
+        ),
+        (29, 5)
+    );
+    // ollama is free to run, so there's nothing to price
+    assert_eq!(client.total_cost, 0.0);
+}